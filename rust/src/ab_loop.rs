@@ -0,0 +1,196 @@
+#![allow(dead_code)]
+
+use gst::prelude::*;
+use gst::{SeekFlags, SeekType};
+
+use crate::clock_time::parse_time;
+
+// Loops the section between A and B without gaps.
+// A seek with the SEGMENT flag makes the pipeline post a SEGMENT_DONE message instead of going EOS
+// when it reaches the stop position. Answering it with another SEGMENT seek that does NOT flush
+// queues the next segment right behind the current one, so the sinks never run dry.
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AbLoop {
+    pub a: Option<gst::ClockTime>,
+    pub b: Option<gst::ClockTime>,
+    /// Are we currently in segment looping mode?
+    active: bool,
+    rate: f64,
+    /// Print the loop messages to stderr, when stdout is taken (e.g. by the progress report)
+    pub log_to_stderr: bool,
+}
+
+impl AbLoop {
+    pub fn new() -> AbLoop {
+        AbLoop {
+            rate: 1.0,
+            ..Default::default()
+        }
+    }
+
+    // "--loop <A> <B>"
+    pub fn from_args(args: &[String]) -> Option<Result<AbLoop, String>> {
+        let index = args.iter().position(|arg| arg == "--loop")?;
+
+        let point = |offset: usize| -> Result<gst::ClockTime, String> {
+            let text = args
+                .get(index + offset)
+                .ok_or("--loop needs two positions")?;
+            parse_time(text).ok_or_else(|| format!("invalid loop position '{}'", text))
+        };
+
+        let (a, b) = match (point(1), point(2)) {
+            (Ok(a), Ok(b)) => (a, b),
+            (Err(err), _) | (_, Err(err)) => return Some(Err(err)),
+        };
+        if a >= b {
+            return Some(Err("the loop start must be before its end".to_string()));
+        }
+
+        Some(Ok(AbLoop {
+            a: Some(a),
+            b: Some(b),
+            ..AbLoop::new()
+        }))
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn points(&self) -> Option<(gst::ClockTime, gst::ClockTime)> {
+        match (self.a, self.b) {
+            (Some(a), Some(b)) if a < b => Some((a, b)),
+            _ => None,
+        }
+    }
+
+    // Starts looping: flushes to A (or to B when playing backwards), unless the position is
+    // already between A and B (e.g. after a seek to a frame), then the first round starts there
+    pub fn start(&mut self, element: &gst::Element, rate: f64) -> bool {
+        let (a, b) = match self.points() {
+            Some(points) => points,
+            None => {
+                eprintln!("Both loop points must be set, A before B\r");
+                return false;
+            }
+        };
+
+        let position = element
+            .query_position::<gst::ClockTime>()
+            .filter(|position| (a..b).contains(position));
+        let (start, stop) = match position {
+            Some(position) if rate > 0. => (position, b),
+            Some(position) => (a, position),
+            None => (a, b),
+        };
+
+        self.rate = rate;
+        self.active = segment_seek(element, rate, SeekFlags::FLUSH, start, stop);
+        if self.active {
+            self.log(&format!("Looping from {} to {}", a, b));
+        }
+        self.active
+    }
+
+    // Changes the rate while looping without jumping back to the start of the loop
+    pub fn set_rate(&mut self, element: &gst::Element, rate: f64) -> bool {
+        let (a, b) = match self.points() {
+            Some(points) if self.active => points,
+            _ => return false,
+        };
+
+        let position = match element.query_position::<gst::ClockTime>() {
+            Some(position) => position.max(a).min(b),
+            None => return false,
+        };
+
+        let done = if rate > 0. {
+            segment_seek(element, rate, SeekFlags::FLUSH, position, b)
+        } else {
+            segment_seek(element, rate, SeekFlags::FLUSH, a, position)
+        };
+        if done {
+            self.rate = rate;
+        }
+        done
+    }
+
+    // Leaves looping mode: plays on from the current position until the end of the stream
+    // (or its start when playing backwards)
+    pub fn stop(&mut self, element: &gst::Element) {
+        if !self.active {
+            return;
+        }
+        self.active = false;
+
+        if let Some(position) = element.query_position::<gst::ClockTime>() {
+            let flags = SeekFlags::FLUSH | SeekFlags::ACCURATE;
+            // As in chapter-13, when going backwards the position is where the segment stops
+            let _ = if self.rate > 0. {
+                element.seek(
+                    self.rate,
+                    flags,
+                    SeekType::Set,
+                    position,
+                    SeekType::End,
+                    gst::ClockTime::ZERO,
+                )
+            } else {
+                element.seek(
+                    self.rate,
+                    flags,
+                    SeekType::Set,
+                    gst::ClockTime::ZERO,
+                    SeekType::Set,
+                    position,
+                )
+            };
+        }
+        self.log("Loop stopped");
+    }
+
+    fn log(&self, message: &str) {
+        if self.log_to_stderr {
+            eprintln!("{}\r", message);
+        } else {
+            println!("{}\r", message);
+        }
+    }
+
+    // Must be given the bus messages, queues the next round of the loop on SEGMENT_DONE
+    pub fn handle_message(&mut self, element: &gst::Element, msg: &gst::Message) {
+        if !self.active {
+            return;
+        }
+
+        if let gst::MessageView::SegmentDone(..) = msg.view() {
+            if let Some((a, b)) = self.points() {
+                segment_seek(element, self.rate, SeekFlags::empty(), a, b);
+            }
+        }
+    }
+}
+
+fn segment_seek(
+    element: &gst::Element,
+    rate: f64,
+    flags: SeekFlags,
+    start: gst::ClockTime,
+    stop: gst::ClockTime,
+) -> bool {
+    let res = element.seek(
+        rate,
+        flags | SeekFlags::SEGMENT | SeekFlags::ACCURATE,
+        SeekType::Set,
+        start,
+        SeekType::Set,
+        stop,
+    );
+
+    if res.is_err() {
+        eprintln!("Segment seek from {} to {} failed\r", start, stop);
+    }
+    res.is_ok()
+}
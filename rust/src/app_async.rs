@@ -0,0 +1,284 @@
+#![allow(dead_code)]
+
+use futures::{Sink, Stream};
+use gst::prelude::*;
+use gst_app::{AppSink, AppSrc};
+
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+// The appsink and appsrc as futures, instead of callbacks.
+// A SampleStream is a Stream of the samples of an appsink: polling pulls the next one if there is
+// one, otherwise the new-sample (or eos) callback wakes the task up. Samples wait in the appsink
+// until they are pulled, so a slow consumer fills it up, and then blocks the pipeline if the
+// appsink has max-buffers set (and drop unset).
+// A BufferSink is a Sink of the buffers of an appsrc: it is only ready between need-data and
+// enough-data, so sending waits while the appsrc holds max-bytes. Closing it sends an EOS.
+// The callbacks run on the streaming threads, the wakers take the tasks back to their executor
+// (the glib main context in chapter-8-async).
+//
+// Nothing wakes a task when the element is stopped, so the pipeline has to be started before the
+// first poll, and only stopped once the tasks are done (or dropped). Polling after the element
+// went back down to READY or NULL ends the stream, and makes the sink fail with Flushing.
+// A flushing seek doesn't: the samples and need-data after it wake the tasks again.
+
+// Down to READY or NULL, and not on its way back up
+fn is_stopped(element: &gst::Element) -> bool {
+    let (_, current, pending) = element.state(gst::ClockTime::ZERO);
+    current <= gst::State::Ready && pending == gst::State::VoidPending
+}
+
+#[derive(Debug, Default)]
+struct Wakeup {
+    /// For BufferSink, between need-data and enough-data
+    wanted: bool,
+    waker: Option<Waker>,
+}
+
+impl Wakeup {
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SampleStream {
+    appsink: AppSink,
+    wakeup: Arc<Mutex<Wakeup>>,
+}
+
+impl SampleStream {
+    // This takes over the callbacks of the appsink
+    pub fn new(appsink: &AppSink) -> SampleStream {
+        let wakeup = Arc::new(Mutex::new(Wakeup::default()));
+
+        let wakeup_sample = wakeup.clone();
+        let wakeup_eos = wakeup.clone();
+        appsink.set_callbacks(
+            gst_app::AppSinkCallbacks::builder()
+                .new_sample(move |_| {
+                    wakeup_sample.lock().unwrap().wake();
+                    Ok(gst::FlowSuccess::Ok)
+                })
+                .eos(move |_| wakeup_eos.lock().unwrap().wake())
+                .build(),
+        );
+
+        SampleStream {
+            appsink: appsink.clone(),
+            wakeup,
+        }
+    }
+
+    // The next sample without waiting, Ready(None) at the end of the stream or once stopped
+    fn try_next(&self) -> Poll<Option<gst::Sample>> {
+        match self.appsink.try_pull_sample(gst::ClockTime::ZERO) {
+            Some(sample) => Poll::Ready(Some(sample)),
+            None if self.appsink.is_eos() || is_stopped(self.appsink.upcast_ref()) => {
+                Poll::Ready(None)
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl Stream for SampleStream {
+    type Item = gst::Sample;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<gst::Sample>> {
+        if let Poll::Ready(sample) = self.try_next() {
+            return Poll::Ready(sample);
+        }
+
+        // A sample arriving between the first try and here wouldn't wake us, so try again
+        self.wakeup.lock().unwrap().waker = Some(cx.waker().clone());
+        self.try_next()
+    }
+}
+
+impl Drop for SampleStream {
+    fn drop(&mut self) {
+        self.appsink
+            .set_callbacks(gst_app::AppSinkCallbacks::builder().build());
+    }
+}
+
+#[derive(Debug)]
+pub struct BufferSink {
+    appsrc: AppSrc,
+    wakeup: Arc<Mutex<Wakeup>>,
+}
+
+impl BufferSink {
+    // At most max_bytes wait in the appsrc. This takes over its callbacks.
+    pub fn new(appsrc: &AppSrc, max_bytes: u64) -> BufferSink {
+        let wakeup = Arc::new(Mutex::new(Wakeup::default()));
+
+        appsrc.set_max_bytes(max_bytes);
+        // push_buffer() never blocks, enough-data makes us not ready instead
+        appsrc.set_block(false);
+
+        let wakeup_need = wakeup.clone();
+        let wakeup_enough = wakeup.clone();
+        appsrc.set_callbacks(
+            gst_app::AppSrcCallbacks::builder()
+                .need_data(move |_, _size| {
+                    let mut wakeup = wakeup_need.lock().unwrap();
+                    wakeup.wanted = true;
+                    wakeup.wake();
+                })
+                .enough_data(move |_| wakeup_enough.lock().unwrap().wanted = false)
+                .build(),
+        );
+
+        BufferSink {
+            appsrc: appsrc.clone(),
+            wakeup,
+        }
+    }
+}
+
+impl Sink<gst::Buffer> for BufferSink {
+    type Error = gst::FlowError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), gst::FlowError>> {
+        // The last need-data may be from before it was stopped
+        if is_stopped(self.appsrc.upcast_ref()) {
+            return Poll::Ready(Err(gst::FlowError::Flushing));
+        }
+
+        let mut wakeup = self.wakeup.lock().unwrap();
+        if wakeup.wanted {
+            Poll::Ready(Ok(()))
+        } else {
+            wakeup.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, buffer: gst::Buffer) -> Result<(), gst::FlowError> {
+        self.appsrc.push_buffer(buffer).map(|_| ())
+    }
+
+    // Pushed buffers are the appsrc's, there is nothing to flush
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), gst::FlowError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), gst::FlowError>> {
+        Poll::Ready(self.appsrc.end_of_stream().map(|_| ()))
+    }
+}
+
+impl Drop for BufferSink {
+    fn drop(&mut self) {
+        self.appsrc
+            .set_callbacks(gst_app::AppSrcCallbacks::builder().build());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::{future, SinkExt, StreamExt};
+
+    // appsrc ! appsink, with room for a single sample in the appsink
+    fn pipeline() -> (gst::Pipeline, AppSrc, AppSink) {
+        gst::init().unwrap();
+        let pipeline = gst::parse_launch(
+            "appsrc name=src format=time caps=application/x-test ! \
+             appsink name=sink sync=false max-buffers=1",
+        )
+        .unwrap()
+        .downcast::<gst::Pipeline>()
+        .unwrap();
+        let appsrc = pipeline
+            .by_name("src")
+            .unwrap()
+            .downcast::<AppSrc>()
+            .unwrap();
+        let appsink = pipeline
+            .by_name("sink")
+            .unwrap()
+            .downcast::<AppSink>()
+            .unwrap();
+        (pipeline, appsrc, appsink)
+    }
+
+    fn buffer(value: u8) -> gst::Buffer {
+        gst::Buffer::from_slice(vec![value; 16])
+    }
+
+    #[test]
+    fn samples_come_out_in_order_and_end_with_eos() {
+        let (pipeline, appsrc, appsink) = pipeline();
+        let mut sink = BufferSink::new(&appsrc, 1024);
+        let mut samples = SampleStream::new(&appsink);
+        pipeline.set_state(gst::State::Playing).unwrap();
+
+        block_on(async {
+            for value in 0..5 {
+                sink.send(buffer(value)).await.unwrap();
+                let sample = samples.next().await.unwrap();
+                let map = sample.buffer().unwrap().map_readable().unwrap();
+                assert_eq!(map.as_slice(), &[value; 16]);
+            }
+            sink.close().await.unwrap();
+            assert!(samples.next().await.is_none());
+        });
+
+        pipeline.set_state(gst::State::Null).unwrap();
+    }
+
+    #[test]
+    fn sink_waits_while_the_appsrc_is_full() {
+        let (pipeline, appsrc, appsink) = pipeline();
+        let mut sink = BufferSink::new(&appsrc, 64);
+        let mut samples = SampleStream::new(&appsink);
+        pipeline.set_state(gst::State::Playing).unwrap();
+
+        // Nobody pulls, so the appsink blocks the pipeline and the appsrc fills up
+        block_on(sink.send(buffer(0))).unwrap();
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut sent = 1;
+        while sink.poll_ready_unpin(&mut cx).is_ready() {
+            assert!(sent < 20, "the appsrc never got full");
+            sink.start_send_unpin(buffer(sent)).unwrap();
+            sent += 1;
+        }
+
+        // Once everything is pulled the appsrc is empty, and need-data wakes the sink up
+        block_on(async {
+            for _ in 0..sent {
+                samples.next().await.unwrap();
+            }
+            future::poll_fn(|cx| sink.poll_ready_unpin(cx))
+                .await
+                .unwrap();
+        });
+
+        pipeline.set_state(gst::State::Null).unwrap();
+    }
+
+    #[test]
+    fn stopped_elements_end_the_stream() {
+        let (pipeline, appsrc, appsink) = pipeline();
+        let mut sink = BufferSink::new(&appsrc, 1024);
+        let mut samples = SampleStream::new(&appsink);
+        pipeline.set_state(gst::State::Playing).unwrap();
+        block_on(sink.send(buffer(0))).unwrap();
+        pipeline.set_state(gst::State::Null).unwrap();
+
+        block_on(async {
+            // What was already there may still come out
+            while samples.next().await.is_some() {}
+            let ready = future::poll_fn(|cx| sink.poll_ready_unpin(cx)).await;
+            assert_eq!(ready, Err(gst::FlowError::Flushing));
+        });
+    }
+}
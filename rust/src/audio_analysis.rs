@@ -0,0 +1,314 @@
+#![allow(dead_code)]
+
+use std::f64::consts::PI;
+
+use crate::waveform;
+
+// Measures what is in a buffer of raw audio, to check generated signals numerically:
+//  - RMS and peak level of each channel, in dBFS (0 dB is the loudest a format can hold)
+//  - zero-crossing rate, in crossings per second (twice the frequency for a pure tone)
+//  - the magnitude spectrum of all channels mixed together, through an FFT over the largest
+//    power of two of frames in the buffer, with a Hann window against leakage
+
+const MAX_FFT_SIZE: usize = 8192;
+
+#[derive(Debug, Clone, Default)]
+pub struct ChannelStats {
+    pub rms: f64,
+    pub peak: f64,
+    pub zero_crossing_rate: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Analysis {
+    pub channels: Vec<ChannelStats>,
+    /// Magnitudes of the bins from 0 Hz to half the sample rate
+    pub spectrum: Vec<f64>,
+    /// Hz between two bins of the spectrum
+    pub bin_width: f64,
+}
+
+pub fn to_db(value: f64) -> f64 {
+    if value > 0.0 {
+        20.0 * value.log10()
+    } else {
+        f64::NEG_INFINITY
+    }
+}
+
+// The samples of each channel, as values between -1.0 and 1.0
+pub fn deinterleave(info: &gst_audio::AudioInfo, data: &[u8]) -> Vec<Vec<f64>> {
+    let format = info.format_info();
+    let sample_size = (format.width() / 8) as usize;
+    let mut channels = vec![Vec::new(); info.channels() as usize];
+
+    for frame in data.chunks_exact(info.bpf() as usize) {
+        for (channel, bytes) in channels.iter_mut().zip(frame.chunks_exact(sample_size)) {
+            channel.push(waveform::read_sample(&format, bytes));
+        }
+    }
+    channels
+}
+
+pub fn analyze(info: &gst_audio::AudioInfo, data: &[u8]) -> Analysis {
+    let channels = deinterleave(info, data);
+    let rate = f64::from(info.rate());
+
+    let stats = channels
+        .iter()
+        .map(|samples| {
+            if samples.is_empty() {
+                return ChannelStats::default();
+            }
+            let sum_squares: f64 = samples.iter().map(|sample| sample * sample).sum();
+            let crossings = samples
+                .windows(2)
+                .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+                .count();
+
+            ChannelStats {
+                rms: (sum_squares / samples.len() as f64).sqrt(),
+                peak: samples
+                    .iter()
+                    .fold(0.0, |peak, sample| sample.abs().max(peak)),
+                zero_crossing_rate: crossings as f64 * rate / samples.len() as f64,
+            }
+        })
+        .collect();
+
+    let frames = channels.first().map(|samples| samples.len()).unwrap_or(0);
+    let mut size = 1;
+    while size * 2 <= frames.min(MAX_FFT_SIZE) {
+        size *= 2;
+    }
+
+    let (spectrum, bin_width) = if size < 2 {
+        (Vec::new(), 0.0)
+    } else {
+        let mix: Vec<f64> = (0..size)
+            .map(|i| {
+                let sum: f64 = channels.iter().map(|samples| samples[i]).sum();
+                sum / channels.len() as f64
+            })
+            .collect();
+        (magnitude_spectrum(&mix), rate / size as f64)
+    };
+
+    Analysis {
+        channels: stats,
+        spectrum,
+        bin_width,
+    }
+}
+
+// Magnitudes of the first half of the FFT of the samples, whose count must be a power of two,
+// scaled so a full scale sine gives about 1.0 in its bin
+pub fn magnitude_spectrum(samples: &[f64]) -> Vec<f64> {
+    let n = samples.len();
+    let mut re: Vec<f64> = samples
+        .iter()
+        .enumerate()
+        .map(|(i, sample)| sample * 0.5 * (1.0 - (2.0 * PI * i as f64 / n as f64).cos()))
+        .collect();
+    let mut im = vec![0.0; n];
+
+    fft(&mut re, &mut im);
+
+    // The Hann window halves the amplitude, the sine is split between two halves of the spectrum
+    (0..n / 2)
+        .map(|i| (re[i] * re[i] + im[i] * im[i]).sqrt() * 4.0 / n as f64)
+        .collect()
+}
+
+// In-place iterative radix-2 FFT
+fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+
+    // Bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut length = 2;
+    while length <= n {
+        let angle = -2.0 * PI / length as f64;
+        for start in (0..n).step_by(length) {
+            for k in 0..length / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let a = start + k;
+                let b = a + length / 2;
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        length *= 2;
+    }
+}
+
+impl Analysis {
+    // The strongest frequencies, strongest first, skipping the bins right next to one already
+    // taken (a tone spreads over a few bins)
+    pub fn dominant_frequencies(&self, count: usize) -> Vec<(f64, f64)> {
+        let mut bins: Vec<usize> = (1..self.spectrum.len()).collect();
+        bins.sort_by(|&a, &b| {
+            self.spectrum[b]
+                .partial_cmp(&self.spectrum[a])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let mut taken: Vec<usize> = Vec::new();
+        for bin in bins {
+            if taken.len() == count {
+                break;
+            }
+            if taken
+                .iter()
+                .all(|&other| bin.max(other) - bin.min(other) > 2)
+            {
+                taken.push(bin);
+            }
+        }
+
+        taken
+            .into_iter()
+            .map(|bin| (bin as f64 * self.bin_width, self.spectrum[bin]))
+            .collect()
+    }
+
+    pub fn print(&self, pts: Option<gst::ClockTime>) {
+        let mut line = format!("{}", pts.display());
+        for (i, channel) in self.channels.iter().enumerate() {
+            line += &format!(
+                " | ch{}: rms {:.1} dBFS, peak {:.1} dBFS, zcr {:.0}/s",
+                i,
+                to_db(channel.rms),
+                to_db(channel.peak),
+                channel.zero_crossing_rate
+            );
+        }
+        let frequencies: Vec<_> = self
+            .dominant_frequencies(3)
+            .iter()
+            .map(|(frequency, magnitude)| {
+                format!("{:.0} Hz ({:.1} dB)", frequency, to_db(*magnitude))
+            })
+            .collect();
+        if !frequencies.is_empty() {
+            line += &format!(" | spectrum: {}", frequencies.join(", "));
+        }
+        println!("{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+
+    // Interleaves the channels into F64LE frames
+    fn analyze_channels(channels: &[Vec<f64>]) -> Analysis {
+        gst::init().unwrap();
+        let info = gst_audio::AudioInfo::builder(
+            gst_audio::AudioFormat::F64le,
+            RATE,
+            channels.len() as u32,
+        )
+        .build()
+        .unwrap();
+        let mut data = Vec::new();
+        for i in 0..channels[0].len() {
+            for channel in channels {
+                data.extend_from_slice(&channel[i].to_le_bytes());
+            }
+        }
+        analyze(&info, &data)
+    }
+
+    // A full scale sine, over a whole number of periods
+    fn sine(freq: f64, frames: usize) -> Vec<f64> {
+        (0..frames)
+            .map(|i| (2.0 * PI * freq * i as f64 / f64::from(RATE)).sin())
+            .collect()
+    }
+
+    fn assert_close(value: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (value - expected).abs() <= tolerance,
+            "{} isn't {} ± {}",
+            value,
+            expected,
+            tolerance
+        );
+    }
+
+    #[test]
+    fn full_scale_sine_levels() {
+        let analysis = analyze_channels(&[sine(1000.0, 4800)]);
+        let channel = &analysis.channels[0];
+        assert_close(channel.rms, 0.5f64.sqrt(), 1e-6);
+        assert_close(channel.peak, 1.0, 1e-6);
+        assert_close(to_db(channel.peak), 0.0, 1e-3);
+        assert_close(to_db(channel.rms), -3.01, 0.01);
+    }
+
+    #[test]
+    fn sine_frequency_and_crossings() {
+        let analysis = analyze_channels(&[sine(1000.0, 8192)]);
+
+        // 8192 frames at 48 kHz, 5.86 Hz per bin
+        assert_eq!(analysis.spectrum.len(), 4096);
+        assert_close(analysis.bin_width, 48000.0 / 8192.0, 1e-9);
+        let (frequency, magnitude) = analysis.dominant_frequencies(1)[0];
+        assert_close(frequency, 1000.0, analysis.bin_width);
+        // 1000 Hz falls between two bins, the Hann window loses up to 1.4 dB there
+        assert_close(magnitude, 1.0, 0.16);
+
+        // Two crossings per period
+        assert_close(analysis.channels[0].zero_crossing_rate, 2000.0, 10.0);
+    }
+
+    #[test]
+    fn channels_are_measured_apart() {
+        let analysis = analyze_channels(&[sine(1000.0, 4800), vec![0.0; 4800]]);
+        assert_close(analysis.channels[0].peak, 1.0, 1e-6);
+        assert_eq!(analysis.channels[1].peak, 0.0);
+        // The spectrum is of the mix of both
+        let (_, magnitude) = analysis.dominant_frequencies(1)[0];
+        assert_close(magnitude, 0.5, 0.08);
+    }
+
+    #[test]
+    fn silence_gives_zeros() {
+        let analysis = analyze_channels(&[vec![0.0; 4800]]);
+        let channel = &analysis.channels[0];
+        assert_eq!(channel.rms, 0.0);
+        assert_eq!(channel.peak, 0.0);
+        assert_eq!(channel.zero_crossing_rate, 0.0);
+        assert_eq!(to_db(channel.rms), f64::NEG_INFINITY);
+        assert_eq!(analysis.spectrum.len(), 2048);
+        assert!(analysis.spectrum.iter().all(|&magnitude| magnitude == 0.0));
+    }
+
+    #[test]
+    fn empty_buffer() {
+        let analysis = analyze_channels(&[Vec::new()]);
+        assert_eq!(analysis.channels[0].rms, 0.0);
+        assert!(analysis.spectrum.is_empty());
+        assert!(analysis.dominant_frequencies(3).is_empty());
+    }
+}
@@ -0,0 +1,319 @@
+#![allow(dead_code)]
+
+use gst_audio::AudioChannelPosition as Position;
+
+use crate::clock_time;
+
+// The format of the audio we generate, given with
+//   --format <S16, S24, S32, F32, F64 or any GStreamer format name like S24_32BE>
+//   --channels <count> --rate <samples per second> --buffer-duration <time, e.g. 20ms>
+// Up to 8 channels get the usual speaker positions (mono, stereo, 2.1, quad, 5.0, 5.1, 6.1, 7.1),
+// more channels are left unpositioned.
+
+pub const DEFAULT_RATE: u32 = 44_100;
+
+#[derive(Debug, Clone)]
+pub struct AudioConfig {
+    pub format: gst_audio::AudioFormat,
+    pub channels: u32,
+    pub rate: u32,
+    pub buffer_duration: gst::ClockTime,
+}
+
+impl Default for AudioConfig {
+    fn default() -> AudioConfig {
+        AudioConfig {
+            format: gst_audio::AudioFormat::S16le,
+            channels: 1,
+            rate: DEFAULT_RATE,
+            buffer_duration: gst::ClockTime::from_mseconds(10),
+        }
+    }
+}
+
+pub fn parse_format(name: &str) -> Option<gst_audio::AudioFormat> {
+    let format = match name {
+        "S16" => gst_audio::AudioFormat::S16le,
+        "S24" => gst_audio::AudioFormat::S24le,
+        "S32" => gst_audio::AudioFormat::S32le,
+        "F32" => gst_audio::AudioFormat::F32le,
+        "F64" => gst_audio::AudioFormat::F64le,
+        _ => gst_audio::AudioFormat::from_string(name),
+    };
+
+    match format {
+        gst_audio::AudioFormat::Unknown | gst_audio::AudioFormat::Encoded => None,
+        format => Some(format),
+    }
+}
+
+// The usual layouts, in the order GStreamer expects the channels
+pub fn channel_positions(channels: u32) -> Option<Vec<Position>> {
+    let stereo = [Position::FrontLeft, Position::FrontRight];
+    let positions: Vec<Position> = match channels {
+        1 => vec![Position::Mono],
+        2 => stereo.to_vec(),
+        3 => [&stereo[..], &[Position::Lfe1]].concat(),
+        4 => [&stereo[..], &[Position::RearLeft, Position::RearRight]].concat(),
+        5 => [
+            &stereo[..],
+            &[
+                Position::FrontCenter,
+                Position::RearLeft,
+                Position::RearRight,
+            ],
+        ]
+        .concat(),
+        6 => [
+            &stereo[..],
+            &[
+                Position::FrontCenter,
+                Position::Lfe1,
+                Position::RearLeft,
+                Position::RearRight,
+            ],
+        ]
+        .concat(),
+        7 => [
+            &stereo[..],
+            &[
+                Position::FrontCenter,
+                Position::Lfe1,
+                Position::RearCenter,
+                Position::SideLeft,
+                Position::SideRight,
+            ],
+        ]
+        .concat(),
+        8 => [
+            &stereo[..],
+            &[
+                Position::FrontCenter,
+                Position::Lfe1,
+                Position::RearLeft,
+                Position::RearRight,
+                Position::SideLeft,
+                Position::SideRight,
+            ],
+        ]
+        .concat(),
+        _ => return None,
+    };
+    Some(positions)
+}
+
+impl AudioConfig {
+    pub fn from_args(args: &[String]) -> Result<AudioConfig, String> {
+        let mut config = AudioConfig::default();
+
+        let value = |name: &str| -> Result<Option<&String>, String> {
+            match args.iter().position(|arg| arg == name) {
+                Some(i) => args
+                    .get(i + 1)
+                    .map(Some)
+                    .ok_or_else(|| format!("{} needs a value", name)),
+                None => Ok(None),
+            }
+        };
+        let invalid = |name: &str, value: &str| format!("invalid value for {}: {}", name, value);
+
+        if let Some(format) = value("--format")? {
+            config.format = parse_format(format).ok_or_else(|| invalid("--format", format))?;
+        }
+        if let Some(channels) = value("--channels")? {
+            config.channels = channels
+                .parse()
+                .ok()
+                .filter(|&channels| channels > 0)
+                .ok_or_else(|| invalid("--channels", channels))?;
+        }
+        if let Some(rate) = value("--rate")? {
+            config.rate = rate
+                .parse()
+                .ok()
+                .filter(|&rate| rate > 0)
+                .ok_or_else(|| invalid("--rate", rate))?;
+        }
+        if let Some(duration) = value("--buffer-duration")? {
+            config.buffer_duration = clock_time::parse_time(duration)
+                .filter(|duration| duration.nseconds() > 0)
+                .ok_or_else(|| invalid("--buffer-duration", duration))?;
+        }
+
+        Ok(config)
+    }
+
+    pub fn info(&self) -> Result<gst_audio::AudioInfo, glib::BoolError> {
+        let builder = gst_audio::AudioInfo::builder(self.format, self.rate, self.channels);
+        match channel_positions(self.channels) {
+            Some(positions) => builder.positions(&positions).build(),
+            None => builder.flags(gst_audio::AudioFlags::UNPOSITIONED).build(),
+        }
+    }
+
+    // Samples (per channel) in each buffer, at least one
+    pub fn samples_per_buffer(&self) -> u64 {
+        let samples = u128::from(self.buffer_duration.nseconds()) * u128::from(self.rate)
+            / u128::from(gst::ClockTime::SECOND.nseconds());
+        (samples as u64).max(1)
+    }
+}
+
+// The time of the first sample after `samples` samples. Buffer durations are taken as the
+// difference of two of these, so they add up exactly to the timestamps instead of drifting.
+pub fn samples_to_time(samples: u64, rate: u32) -> gst::ClockTime {
+    gst::ClockTime::SECOND
+        .mul_div_floor(samples, u64::from(rate))
+        .expect("u64 overflow")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gst_audio::AudioFormat;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn config(rate: u32, buffer_duration: gst::ClockTime) -> AudioConfig {
+        AudioConfig {
+            rate,
+            buffer_duration,
+            ..AudioConfig::default()
+        }
+    }
+
+    #[test]
+    fn parses_every_option() {
+        gst::init().unwrap();
+        let config = AudioConfig::from_args(&args(&["app"])).unwrap();
+        assert_eq!(config.format, AudioFormat::S16le);
+        assert_eq!(config.channels, 1);
+        assert_eq!(config.rate, DEFAULT_RATE);
+        assert_eq!(config.buffer_duration, gst::ClockTime::from_mseconds(10));
+
+        let config = AudioConfig::from_args(&args(&[
+            "app",
+            "--format",
+            "S24_32BE",
+            "--channels",
+            "6",
+            "--rate",
+            "48000",
+            "--buffer-duration",
+            "20ms",
+        ]))
+        .unwrap();
+        assert_eq!(config.format, AudioFormat::S2432be);
+        assert_eq!(config.channels, 6);
+        assert_eq!(config.rate, 48000);
+        assert_eq!(config.buffer_duration, gst::ClockTime::from_mseconds(20));
+    }
+
+    #[test]
+    fn short_format_names_are_little_endian() {
+        gst::init().unwrap();
+        assert_eq!(parse_format("S16"), Some(AudioFormat::S16le));
+        assert_eq!(parse_format("S24"), Some(AudioFormat::S24le));
+        assert_eq!(parse_format("S32"), Some(AudioFormat::S32le));
+        assert_eq!(parse_format("F32"), Some(AudioFormat::F32le));
+        assert_eq!(parse_format("F64"), Some(AudioFormat::F64le));
+        assert_eq!(parse_format("U8"), Some(AudioFormat::U8));
+        assert_eq!(parse_format("encoded"), None);
+        assert_eq!(parse_format("S17"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        gst::init().unwrap();
+        let error = |options: &[&str]| {
+            let mut all = vec!["app"];
+            all.extend_from_slice(options);
+            AudioConfig::from_args(&args(&all)).unwrap_err()
+        };
+
+        assert_eq!(
+            error(&["--format", "S17"]),
+            "invalid value for --format: S17"
+        );
+        assert_eq!(
+            error(&["--channels", "0"]),
+            "invalid value for --channels: 0"
+        );
+        assert_eq!(
+            error(&["--channels", "two"]),
+            "invalid value for --channels: two"
+        );
+        assert_eq!(error(&["--rate", "0"]), "invalid value for --rate: 0");
+        assert_eq!(
+            error(&["--buffer-duration", "0ms"]),
+            "invalid value for --buffer-duration: 0ms"
+        );
+        assert_eq!(error(&["--rate"]), "--rate needs a value");
+    }
+
+    #[test]
+    fn positions_the_usual_layouts() {
+        gst::init().unwrap();
+        for channels in 1..=8 {
+            let positions = channel_positions(channels).unwrap();
+            assert_eq!(positions.len(), channels as usize);
+
+            // GStreamer accepts them as they are, in its channel order
+            let config = AudioConfig {
+                channels,
+                ..AudioConfig::default()
+            };
+            let info = config.info().unwrap();
+            assert_eq!(info.positions().unwrap(), &positions[..]);
+            assert!(!info.flags().contains(gst_audio::AudioFlags::UNPOSITIONED));
+        }
+        assert_eq!(channel_positions(1), Some(vec![Position::Mono]));
+        assert_eq!(channel_positions(6).unwrap()[3], Position::Lfe1);
+
+        // Past 8 channels they are left unpositioned
+        assert_eq!(channel_positions(9), None);
+        let config = AudioConfig {
+            channels: 9,
+            ..AudioConfig::default()
+        };
+        let info = config.info().unwrap();
+        assert!(info.flags().contains(gst_audio::AudioFlags::UNPOSITIONED));
+    }
+
+    #[test]
+    fn samples_per_buffer_rounds_down() {
+        let ms = gst::ClockTime::from_mseconds;
+        assert_eq!(config(48000, ms(10)).samples_per_buffer(), 480);
+        assert_eq!(config(44100, ms(10)).samples_per_buffer(), 441);
+        // 220.5 samples
+        assert_eq!(config(22050, ms(10)).samples_per_buffer(), 220);
+        // 0.441 samples
+        assert_eq!(
+            config(44100, gst::ClockTime::from_useconds(10)).samples_per_buffer(),
+            1
+        );
+    }
+
+    #[test]
+    fn sample_times_round_down_without_drifting() {
+        assert_eq!(
+            samples_to_time(441, 44100),
+            gst::ClockTime::from_mseconds(10)
+        );
+        // 22675.73 ns
+        assert_eq!(samples_to_time(1, 44100).nseconds(), 22_675);
+        // 9977324.26 ns
+        assert_eq!(samples_to_time(220, 22050).nseconds(), 9_977_324);
+
+        // The buffer durations add up to the timestamp of the next buffer
+        let duration = |buffer: u64| {
+            samples_to_time((buffer + 1) * 220, 22050) - samples_to_time(buffer * 220, 22050)
+        };
+        let total: u64 = (0..100).map(|buffer| duration(buffer).nseconds()).sum();
+        assert_eq!(total, samples_to_time(100 * 220, 22050).nseconds());
+        // 9977324 ns each would have been 997732400 ns
+        assert_eq!(total, 997_732_426);
+    }
+}
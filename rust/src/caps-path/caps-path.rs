@@ -0,0 +1,187 @@
+use glib::translate::IntoGlib;
+use gst::prelude::*;
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+
+#[path = "../caps_compat.rs"]
+mod caps_compat;
+
+// Finds chains of elements converting some caps into other caps, from the registry alone.
+// USAGE: caps-path <input caps> <target caps> [max length]
+// e.g.   caps-path video/x-h264 video/x-raw,format=RGB
+//
+// Every factory that transforms data (decoders, encoders, converters, parsers...) is a node.
+// A factory can follow the input if one of its sink templates accepts the input caps, and can follow
+// another factory if it accepts what that one's src templates produce. A breadth-first search
+// gives the shortest chains, which are then ranked by the ranks of their elements.
+
+const DEFAULT_MAX_LENGTH: usize = 4;
+// How many chains we print, and how many we collect before giving up on the others
+const MAX_PRINTED: usize = 10;
+const MAX_CHAINS: usize = 500;
+
+struct Node {
+    factory: gst::ElementFactory,
+    src_caps: gst::Caps,
+}
+
+fn candidate_factories() -> Vec<Node> {
+    gst::ElementFactory::list_get_elements(gst::ElementFactoryType::ANY, gst::Rank::None)
+        .into_iter()
+        .filter(|factory| {
+            let klass = factory.metadata("klass").unwrap_or("");
+            !klass.contains("Source")
+                && !klass.contains("Sink")
+                && !caps_compat::is_passthrough(factory)
+        })
+        .map(|factory| Node {
+            src_caps: caps_compat::template_caps(&factory, gst::PadDirection::Src),
+            factory,
+        })
+        .filter(|node| !node.src_caps.is_empty())
+        .collect()
+}
+
+// All shortest chains (as indices into the nodes) from the input to the target
+fn shortest_chains(
+    nodes: &[Node],
+    input: &gst::Caps,
+    target: &gst::Caps,
+    max_length: usize,
+) -> Vec<Vec<usize>> {
+    // For every node reached, the nodes of the previous level it can follow (None for the input)
+    let mut parents: HashMap<usize, Vec<Option<usize>>> = HashMap::new();
+    let mut visited = HashSet::new();
+
+    let mut level: Vec<usize> = (0..nodes.len())
+        .filter(|&i| nodes[i].factory.can_sink_any_caps(input))
+        .collect();
+    for &i in &level {
+        parents.insert(i, vec![None]);
+        visited.insert(i);
+    }
+
+    for _ in 0..max_length {
+        let goals: Vec<usize> = level
+            .iter()
+            .copied()
+            .filter(|&i| nodes[i].src_caps.can_intersect(target))
+            .collect();
+        if !goals.is_empty() {
+            let mut chains = Vec::new();
+            for goal in goals {
+                collect_chains(goal, &parents, &mut vec![goal], &mut chains);
+            }
+            return chains;
+        }
+
+        let mut next_level = Vec::new();
+        for (i, node) in nodes.iter().enumerate() {
+            if visited.contains(&i) {
+                continue;
+            }
+
+            for &previous in &level {
+                if node.factory.can_sink_any_caps(&nodes[previous].src_caps) {
+                    parents.entry(i).or_default().push(Some(previous));
+                }
+            }
+            if parents.contains_key(&i) {
+                next_level.push(i);
+            }
+        }
+
+        if next_level.is_empty() {
+            break;
+        }
+        visited.extend(next_level.iter().copied());
+        level = next_level;
+    }
+
+    Vec::new()
+}
+
+// Walks back from a node to the input through every parent, `chain` is built in reverse
+fn collect_chains(
+    node: usize,
+    parents: &HashMap<usize, Vec<Option<usize>>>,
+    chain: &mut Vec<usize>,
+    chains: &mut Vec<Vec<usize>>,
+) {
+    for parent in &parents[&node] {
+        if chains.len() >= MAX_CHAINS {
+            return;
+        }
+
+        match *parent {
+            None => chains.push(chain.iter().rev().copied().collect()),
+            Some(parent) => {
+                chain.push(parent);
+                collect_chains(parent, parents, chain, chains);
+                chain.pop();
+            }
+        }
+    }
+}
+
+fn main() {
+    if let Err(err) = gst::init() {
+        eprintln!("Failed to initialize Gst: {}", err);
+        return;
+    }
+
+    let args: Vec<_> = env::args().collect();
+    if args.len() < 3 {
+        eprintln!("USAGE: caps-path <input caps> <target caps> [max length]");
+        return;
+    }
+
+    let (input, target) = match (args[1].parse::<gst::Caps>(), args[2].parse::<gst::Caps>()) {
+        (Ok(input), Ok(target)) => (input, target),
+        _ => {
+            eprintln!("Invalid caps");
+            return;
+        }
+    };
+    let max_length = args
+        .get(3)
+        .and_then(|arg| arg.parse().ok())
+        .unwrap_or(DEFAULT_MAX_LENGTH);
+
+    if input.can_intersect(&target) {
+        println!("{} is already compatible with {}", input, target);
+        return;
+    }
+
+    let nodes = candidate_factories();
+    let mut chains = shortest_chains(&nodes, &input, &target, max_length);
+    if chains.is_empty() {
+        println!("No chain of at most {} elements found", max_length);
+        return;
+    }
+
+    // Best ranked elements first, decodebin would pick them too
+    let chain_rank = |chain: &Vec<usize>| -> i32 {
+        chain
+            .iter()
+            .map(|&i| nodes[i].factory.rank().into_glib())
+            .sum()
+    };
+    chains.sort_by_key(|chain| std::cmp::Reverse(chain_rank(chain)));
+
+    println!(
+        "{} shortest chains of {} elements from {} to {}:",
+        chains.len(),
+        chains[0].len(),
+        input,
+        target
+    );
+    for chain in chains.iter().take(MAX_PRINTED) {
+        let names: Vec<_> = chain
+            .iter()
+            .map(|&i| nodes[i].factory.name().to_string())
+            .collect();
+        println!("  [rank {:>4}] {}", chain_rank(chain), names.join(" ! "));
+    }
+}
@@ -0,0 +1,141 @@
+#![allow(dead_code)]
+
+use gst::prelude::*;
+
+use std::collections::BTreeSet;
+
+// Helpers to reason about caps before any pad exists, from the pad templates of the factories only.
+// This is the "early refusal" the pad templates allow: if the caps of a src template and a sink template
+// don't intersect, the elements can never link, whatever happens during negotiation.
+
+// All the caps the templates of a factory accept (sink) or produce (src), merged together
+pub fn template_caps(factory: &gst::ElementFactory, direction: gst::PadDirection) -> gst::Caps {
+    let mut caps = gst::Caps::new_empty();
+    {
+        let caps = caps.get_mut().unwrap();
+        for template in factory.static_pad_templates() {
+            if template.direction() == direction {
+                caps.append(template.caps());
+            }
+        }
+    }
+    caps
+}
+
+// Explains why two caps don't intersect, one line per problem.
+// Structures only intersect with structures of the same media type and the same caps features
+// (memory:SystemMemory unless said otherwise), and then every field they both have must
+// intersect (a field that only one side has doesn't constrain anything).
+pub fn conflicts(src: &gst::CapsRef, sink: &gst::CapsRef) -> Vec<String> {
+    let mut conflicts = Vec::new();
+
+    for (side, caps) in [("src", src), ("sink", sink)] {
+        if caps.is_empty() {
+            conflicts.push(format!("{} caps are EMPTY", side));
+        }
+    }
+    if !conflicts.is_empty() {
+        return conflicts;
+    }
+
+    let same_type_pairs: Vec<_> = src
+        .iter_with_features()
+        .flat_map(|src_pair| {
+            sink.iter_with_features()
+                .filter(move |(sink_structure, _)| sink_structure.name() == src_pair.0.name())
+                .map(move |sink_pair| (src_pair, sink_pair))
+        })
+        .collect();
+
+    if same_type_pairs.is_empty() {
+        let names = |caps: &gst::CapsRef| {
+            let names: BTreeSet<_> = caps.iter().map(|s| s.name().to_string()).collect();
+            names.into_iter().collect::<Vec<_>>().join(", ")
+        };
+        conflicts.push(format!(
+            "media type: src produces {} but sink accepts {}",
+            names(src),
+            names(sink)
+        ));
+        return conflicts;
+    }
+
+    for ((src_structure, src_features), (sink_structure, sink_features)) in same_type_pairs {
+        if !src_features.is_any()
+            && !sink_features.is_any()
+            && !src_features.is_equal(sink_features)
+        {
+            let conflict = format!(
+                "{} features: src has {} but sink wants {}",
+                src_structure.name(),
+                src_features,
+                sink_features
+            );
+            if !conflicts.contains(&conflict) {
+                conflicts.push(conflict);
+            }
+        }
+
+        for (field, src_value) in src_structure.iter() {
+            let sink_value = match sink_structure.value(field) {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+
+            if !src_value.can_intersect(sink_value) {
+                let serialize = |value: &glib::SendValue| {
+                    value
+                        .serialize()
+                        .map(|value| value.to_string())
+                        .unwrap_or_else(|_| format!("{:?}", value))
+                };
+                let conflict = format!(
+                    "{} {}: src has {} but sink wants {}",
+                    src_structure.name(),
+                    field,
+                    serialize(src_value),
+                    serialize(sink_value)
+                );
+                if !conflicts.contains(&conflict) {
+                    conflicts.push(conflict);
+                }
+            }
+        }
+    }
+
+    // Nothing we look at explains it, say so rather than nothing
+    if conflicts.is_empty() {
+        conflicts.push(format!(
+            "none of the {} src structures intersects one of the {} sink structures",
+            src.size(),
+            sink.size()
+        ));
+    }
+
+    conflicts
+}
+
+// Factories accepting ANY caps (queue, tee, identity...) fit everywhere but never convert anything
+pub fn is_passthrough(factory: &gst::ElementFactory) -> bool {
+    factory
+        .static_pad_templates()
+        .iter()
+        .any(|template| template.caps().is_any())
+}
+
+// Elements with a sink template accepting `from` and a src template producing `to`,
+// best ranked first
+pub fn bridging_factories(from: &gst::CapsRef, to: &gst::CapsRef) -> Vec<gst::ElementFactory> {
+    let mut factories: Vec<_> =
+        gst::ElementFactory::list_get_elements(gst::ElementFactoryType::ANY, gst::Rank::None)
+            .into_iter()
+            .filter(|factory| {
+                !is_passthrough(factory)
+                    && factory.can_sink_any_caps(from)
+                    && factory.can_src_any_caps(to)
+            })
+            .collect();
+
+    factories.sort_by_key(|factory| std::cmp::Reverse(factory.rank()));
+    factories
+}
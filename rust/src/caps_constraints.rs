@@ -0,0 +1,157 @@
+#![allow(dead_code)]
+
+use gst::prelude::*;
+
+use crate::caps_compat;
+
+// Forces formats between elements with capsfilters.
+// A constraint "source:sink=audio/x-raw,rate=48000,channels=2,format=F32LE" replaces the link
+// between the elements named source and sink with source ! capsfilter ! sink, so whatever gets
+// negotiated on that link has to match the caps. If nothing can, negotiation fails and the
+// report says which side of the capsfilter couldn't go along with it.
+
+#[derive(Debug, Clone)]
+pub struct CapsConstraint {
+    pub upstream: String,
+    pub downstream: String,
+    pub caps: gst::Caps,
+}
+
+impl CapsConstraint {
+    pub fn parse(text: &str) -> Result<CapsConstraint, String> {
+        let invalid = || format!("invalid constraint '{}', expected <up>:<down>=<caps>", text);
+
+        let (link, caps) = text.split_once('=').ok_or_else(invalid)?;
+        let (upstream, downstream) = link.split_once(':').ok_or_else(invalid)?;
+        let caps = caps
+            .parse::<gst::Caps>()
+            .map_err(|_| format!("invalid caps '{}'", caps))?;
+
+        Ok(CapsConstraint {
+            upstream: upstream.to_string(),
+            downstream: downstream.to_string(),
+            caps,
+        })
+    }
+
+    // Every "--caps <constraint>" of the arguments
+    pub fn from_args(args: &[String]) -> Result<Vec<CapsConstraint>, String> {
+        args.iter()
+            .zip(args.iter().skip(1))
+            .filter(|(arg, _)| *arg == "--caps")
+            .map(|(_, constraint)| CapsConstraint::parse(constraint))
+            .collect()
+    }
+
+    pub fn filter_name(&self) -> String {
+        format!("{}_{}_caps", self.upstream, self.downstream)
+    }
+}
+
+// Puts a capsfilter on each constrained link. Must be done before the pipeline leaves NULL.
+pub fn apply(pipeline: &gst::Bin, constraints: &[CapsConstraint]) -> Result<(), String> {
+    for constraint in constraints {
+        let find = |name: &str| {
+            pipeline
+                .by_name(name)
+                .ok_or_else(|| format!("no element named {}", name))
+        };
+        let upstream = find(&constraint.upstream)?;
+        let downstream = find(&constraint.downstream)?;
+
+        let filter = gst::ElementFactory::make("capsfilter", Some(&constraint.filter_name()))
+            .map_err(|err| err.to_string())?;
+        filter
+            .set_property("caps", &constraint.caps)
+            .map_err(|err| err.to_string())?;
+
+        upstream.unlink(&downstream);
+        pipeline.add(&filter).map_err(|err| err.to_string())?;
+        gst::Element::link_many(&[&upstream, &filter, &downstream]).map_err(|_| {
+            format!(
+                "{} and {} can never link with {}",
+                constraint.upstream, constraint.downstream, constraint.caps
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+// Shows the caps negotiated on every link, and for each constraint that is not satisfied,
+// whether the upstream element can't produce them or the downstream element can't take them.
+pub fn print_report(pipeline: &gst::Bin, constraints: &[CapsConstraint]) {
+    println!("===== negotiated caps =====");
+    let mut iter = pipeline.iterate_recurse();
+    while let Ok(Some(element)) = iter.next() {
+        for pad in element.src_pads() {
+            let peer = match pad.peer() {
+                Some(peer) => peer,
+                None => continue,
+            };
+            let caps = pad
+                .current_caps()
+                .map(|caps| caps.to_string())
+                .unwrap_or_else(|| "NOT NEGOTIATED".to_string());
+            println!(
+                "{}:{} -> {}:{}: {}",
+                element.name(),
+                pad.name(),
+                peer.parent()
+                    .map(|parent| parent.name().to_string())
+                    .unwrap_or_default(),
+                peer.name(),
+                caps
+            );
+        }
+    }
+
+    for constraint in constraints {
+        let filter = match pipeline.by_name(&constraint.filter_name()) {
+            Some(filter) => filter,
+            None => continue,
+        };
+        let negotiated = filter
+            .static_pad("src")
+            .and_then(|pad| pad.current_caps())
+            .is_some();
+        if negotiated {
+            continue;
+        }
+
+        println!(
+            "Constraint {}:{}={} was not satisfied:",
+            constraint.upstream, constraint.downstream, constraint.caps
+        );
+
+        // Asking the neighbours through the pads of the capsfilter gives what they could do
+        // without the constraint: what upstream can produce and what downstream can take
+        let peer_caps = |pad_name: &str| {
+            filter
+                .static_pad(pad_name)
+                .map(|pad| pad.peer_query_caps(None))
+        };
+        let upstream_caps = peer_caps("sink");
+        let downstream_caps = peer_caps("src");
+
+        for (side, caps) in [("upstream", upstream_caps), ("downstream", downstream_caps)] {
+            let caps = match caps {
+                Some(caps) => caps,
+                None => continue,
+            };
+            let (src, sink) = if side == "upstream" {
+                (&caps, &constraint.caps)
+            } else {
+                (&constraint.caps, &caps)
+            };
+            if src.can_intersect(sink) {
+                println!("  {} side: compatible", side);
+            } else {
+                println!("  {} side:", side);
+                for conflict in caps_compat::conflicts(src, sink) {
+                    println!("    {}", conflict);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,218 @@
+#![allow(dead_code)]
+
+use gst::prelude::*;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Records how caps get negotiated, pad by pad.
+// Negotiation is made of three things flowing between pads:
+//  - CAPS queries: "what could you accept/produce?", answered with caps (EMPTY means nothing)
+//  - ACCEPT_CAPS queries: "would you take exactly these caps?", answered yes or no
+//  - CAPS events: "from now on, the data will have these caps"
+// A probe on every pad catches them as they leave it. Queries are recorded once they come back
+// with their answer, so a refused ACCEPT_CAPS tells which peer rejected which caps.
+// A query also passes the peer pad on its way in, so each pad only records what it issues:
+// downstream queries on src pads, upstream queries on sink pads.
+
+#[derive(Debug, Clone)]
+pub enum TraceKind {
+    CapsEvent,
+    CapsQuery { filter: Option<String> },
+    AcceptCaps { accepted: bool },
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceEntry {
+    /// Time since the tracer was created
+    pub time: Duration,
+    /// "element:pad" the event or query left from
+    pub pad: String,
+    /// "element:pad" of the peer that handled it, if linked
+    pub peer: Option<String>,
+    pub kind: TraceKind,
+    /// The caps of the event or of the ACCEPT_CAPS query, or the answer to the CAPS query
+    pub caps: String,
+}
+
+#[derive(Clone)]
+pub struct CapsTracer {
+    start: Instant,
+    entries: Arc<Mutex<Vec<TraceEntry>>>,
+}
+
+impl Default for CapsTracer {
+    fn default() -> CapsTracer {
+        CapsTracer {
+            start: Instant::now(),
+            entries: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+}
+
+fn pad_path(pad: &gst::Pad) -> String {
+    format!(
+        "{}:{}",
+        pad.parent()
+            .map(|parent| parent.name().to_string())
+            .unwrap_or_default(),
+        pad.name()
+    )
+}
+
+impl CapsTracer {
+    pub fn new() -> CapsTracer {
+        CapsTracer::default()
+    }
+
+    // Traces every pad inside the bin, including the ones of elements and pads added later on
+    pub fn attach(&self, bin: &gst::Bin) {
+        let mut iter = bin.iterate_recurse();
+        while let Ok(Some(element)) = iter.next() {
+            self.attach_element(&element);
+        }
+
+        let tracer = self.clone();
+        bin.connect_deep_element_added(move |_, _, element| {
+            tracer.attach_element(element);
+        });
+    }
+
+    fn attach_element(&self, element: &gst::Element) {
+        for pad in element.pads() {
+            self.attach_pad(&pad);
+        }
+
+        let tracer = self.clone();
+        element.connect_pad_added(move |_, pad| {
+            tracer.attach_pad(pad);
+        });
+    }
+
+    fn attach_pad(&self, pad: &gst::Pad) {
+        let mask = match pad.direction() {
+            gst::PadDirection::Src => {
+                gst::PadProbeType::EVENT_DOWNSTREAM | gst::PadProbeType::QUERY_DOWNSTREAM
+            }
+            gst::PadDirection::Sink => gst::PadProbeType::QUERY_UPSTREAM,
+            _ => return,
+        };
+
+        let tracer = self.clone();
+        pad.add_probe(mask, move |pad, info| {
+            // Queries go through the probe twice: PUSH on the way out, PULL with the answer
+            let answered = info.mask.contains(gst::PadProbeType::PULL);
+
+            let entry = match info.data {
+                Some(gst::PadProbeData::Event(ref event))
+                    if pad.direction() == gst::PadDirection::Src =>
+                {
+                    match event.view() {
+                        gst::EventView::Caps(caps) => {
+                            Some((TraceKind::CapsEvent, caps.caps().to_string()))
+                        }
+                        _ => None,
+                    }
+                }
+                Some(gst::PadProbeData::Query(ref query)) if answered => match query.view() {
+                    gst::QueryView::Caps(q) => Some((
+                        TraceKind::CapsQuery {
+                            filter: q.filter().map(|filter| filter.to_string()),
+                        },
+                        q.result()
+                            .map(|caps| caps.to_string())
+                            .unwrap_or_else(|| "(no answer)".to_string()),
+                    )),
+                    gst::QueryView::AcceptCaps(q) => Some((
+                        TraceKind::AcceptCaps {
+                            accepted: q.result(),
+                        },
+                        q.caps().to_string(),
+                    )),
+                    _ => None,
+                },
+                _ => None,
+            };
+
+            if let Some((kind, caps)) = entry {
+                tracer.entries.lock().unwrap().push(TraceEntry {
+                    time: tracer.start.elapsed(),
+                    pad: pad_path(pad),
+                    peer: pad.peer().map(|peer| pad_path(&peer)),
+                    kind,
+                    caps,
+                });
+            }
+
+            gst::PadProbeReturn::Ok
+        });
+    }
+
+    pub fn entries(&self) -> Vec<TraceEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    pub fn print_timeline(&self) {
+        println!("===== caps negotiation timeline =====");
+        for entry in self.entries() {
+            let peer = entry.peer.as_deref().unwrap_or("(unlinked)");
+            match entry.kind {
+                TraceKind::CapsEvent => println!(
+                    "[{:>10.3} ms] {} -> {} CAPS event: {}",
+                    entry.time.as_secs_f64() * 1000.0,
+                    entry.pad,
+                    peer,
+                    entry.caps
+                ),
+                TraceKind::CapsQuery { ref filter } => println!(
+                    "[{:>10.3} ms] {} -> {} CAPS query (filter {}): {}",
+                    entry.time.as_secs_f64() * 1000.0,
+                    entry.pad,
+                    peer,
+                    filter.as_deref().unwrap_or("none"),
+                    entry.caps
+                ),
+                TraceKind::AcceptCaps { accepted } => println!(
+                    "[{:>10.3} ms] {} -> {} ACCEPT_CAPS {}: {}",
+                    entry.time.as_secs_f64() * 1000.0,
+                    entry.pad,
+                    peer,
+                    if accepted { "accepted" } else { "REJECTED" },
+                    entry.caps
+                ),
+            }
+        }
+    }
+
+    // What went wrong: refused ACCEPT_CAPS and CAPS queries that found nothing in common
+    pub fn print_rejections(&self) {
+        let rejections: Vec<_> = self
+            .entries()
+            .into_iter()
+            .filter(|entry| match entry.kind {
+                TraceKind::AcceptCaps { accepted } => !accepted,
+                TraceKind::CapsQuery { .. } => entry.caps == "EMPTY",
+                TraceKind::CapsEvent => false,
+            })
+            .collect();
+
+        if rejections.is_empty() {
+            println!("No caps were rejected");
+            return;
+        }
+
+        println!("===== rejected caps =====");
+        for entry in rejections {
+            let peer = entry.peer.as_deref().unwrap_or("(unlinked)");
+            match entry.kind {
+                TraceKind::AcceptCaps { .. } => {
+                    println!("{} rejected {} from {}", peer, entry.caps, entry.pad)
+                }
+                _ => println!(
+                    "{} has nothing in common with what {} asked for",
+                    peer, entry.pad
+                ),
+            }
+        }
+    }
+}
@@ -1,16 +1,27 @@
 use gst::prelude::*;
 
+use std::env;
+
 #[path = "../common.rs"]
 mod common;
+#[path = "../decoder_selection.rs"]
+mod decoder_selection;
+
+use decoder_selection::DecoderPolicy;
 
 fn tutorial_main() {
     // Initialize GStreamer
     gst::init().unwrap();
 
+    // Adjust the decoder ranks (--prefer-decoder / --block-decoder) before anything gets autoplugged
+    let args: Vec<_> = env::args().collect();
+    DecoderPolicy::from_args(&args).apply();
+
     // Build the pipeline
     let uri =
         "https://www.freedesktop.org/software/gstreamer-sdk/data/media/sintel_trailer-480p.webm";
     let pipeline = gst::parse_launch(&format!("playbin uri={}", uri)).unwrap();
+    decoder_selection::log_chosen_decoders(pipeline.downcast_ref::<gst::Bin>().unwrap());
 
     // Start playing
     pipeline
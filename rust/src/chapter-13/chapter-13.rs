@@ -8,10 +8,21 @@ use termion::event::Key;
 use termion::input::TermRead;
 use termion::raw::IntoRawMode;
 
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::{io, thread, time};
 
+#[path = "../ab_loop.rs"]
+mod ab_loop;
+#[path = "../clock_time.rs"]
+mod clock_time;
 #[path = "../common.rs"]
 mod common;
+#[path = "../toc.rs"]
+mod toc;
+
+use ab_loop::AbLoop;
+use toc::ChapterList;
 
 // Commands that we get from the terminal and we send to the main thread.
 #[derive(Clone, Copy, PartialEq)]
@@ -21,6 +32,11 @@ enum Command {
     DataRateDown,
     ReverseRate,
     NextFrame,
+    LoopStart,
+    LoopEnd,
+    LoopClear,
+    NextChapter,
+    PreviousChapter,
     Quit,
 }
 
@@ -80,6 +96,15 @@ fn send_seek_event(pipeline: &Element, rate: f64) -> bool {
     */
 }
 
+// While looping, the rate must change through a segment seek or the loop would be left
+fn change_rate(pipeline: &Element, ab_loop: &mut AbLoop, rate: f64) -> bool {
+    if ab_loop.is_active() {
+        ab_loop.set_rate(pipeline, rate)
+    } else {
+        send_seek_event(pipeline, rate)
+    }
+}
+
 // This is where we get the user input from the terminal.
 fn handle_keyboard(ready_tx: glib::Sender<Command>) {
     // We set the terminal in "raw mode" so that we can get the keys without waiting for the user
@@ -95,6 +120,11 @@ fn handle_keyboard(ready_tx: glib::Sender<Command>) {
                 Key::Char('S') => Command::DataRateUp,
                 Key::Char('d' | 'D') => Command::ReverseRate,
                 Key::Char('n' | 'N') => Command::NextFrame,
+                Key::Char('a' | 'A') => Command::LoopStart,
+                Key::Char('b' | 'B') => Command::LoopEnd,
+                Key::Char('l' | 'L') => Command::LoopClear,
+                Key::Char('>' | '.') => Command::NextChapter,
+                Key::Char('<' | ',') => Command::PreviousChapter,
                 Key::Char('q' | 'Q') => Command::Quit,
                 Key::Ctrl('c' | 'C') => Command::Quit,
                 _ => continue,
@@ -122,6 +152,9 @@ USAGE: Choose one of the following options, then press enter:
  'S' to increase playback speed, 's' to decrease playback speed
  'D' to toggle playback direction
  'N' to move to next frame (in the current direction, better in PAUSE)
+ 'A' to set the loop start, 'B' to set the loop end and start looping
+ 'L' to stop looping
+ '>' to jump to the next chapter, '<' to the previous one
  'Q' to quit"
     );
 
@@ -152,6 +185,27 @@ USAGE: Choose one of the following options, then press enter:
     let mut playing = true;
     let mut rate = 1.;
 
+    // The A-B loop is shared with the bus watch, which restarts it on every SEGMENT_DONE.
+    let ab_loop = Rc::new(RefCell::new(AbLoop::new()));
+    // So are the chapters, which the bus watch gets from the TOC messages.
+    let chapters = Rc::new(RefCell::new(ChapterList::default()));
+    let bus = pipeline.bus().expect("Pipeline has no bus");
+    let ab_loop_clone = ab_loop.clone();
+    let chapters_clone = chapters.clone();
+    let pipeline_weak_clone = pipeline.downgrade();
+    bus.add_watch_local(move |_, msg| {
+        if let Some(pipeline) = pipeline_weak_clone.upgrade() {
+            ab_loop_clone.borrow_mut().handle_message(&pipeline, msg);
+        }
+        if let gst::MessageView::Toc(toc_msg) = msg.view() {
+            let (toc, _updated) = toc_msg.toc();
+            toc::print_toc(&toc);
+            *chapters_clone.borrow_mut() = ChapterList::from_toc(&toc);
+        }
+        glib::Continue(true)
+    })
+    .expect("Failed to add bus watch");
+
     ready_rx.attach(Some(&main_loop.context()), move |command: Command| {
         let pipeline = match pipeline_weak.upgrade() {
             Some(pipeline) => pipeline,
@@ -170,17 +224,17 @@ USAGE: Choose one of the following options, then press enter:
                 println!("Setting state to {}\r", status);
             }
             Command::DataRateUp => {
-                if send_seek_event(&pipeline, rate * 2.) {
+                if change_rate(&pipeline, &mut ab_loop.borrow_mut(), rate * 2.) {
                     rate *= 2.;
                 }
             }
             Command::DataRateDown => {
-                if send_seek_event(&pipeline, rate / 2.) {
+                if change_rate(&pipeline, &mut ab_loop.borrow_mut(), rate / 2.) {
                     rate /= 2.;
                 }
             }
             Command::ReverseRate => {
-                if send_seek_event(&pipeline, rate * -1.) {
+                if change_rate(&pipeline, &mut ab_loop.borrow_mut(), rate * -1.) {
                     rate *= -1.;
                 }
             }
@@ -195,6 +249,52 @@ USAGE: Choose one of the following options, then press enter:
                 }
                 */
             }
+            Command::LoopStart => {
+                let mut ab_loop = ab_loop.borrow_mut();
+                ab_loop.a = pipeline.query_position::<gst::ClockTime>();
+                println!("Loop start set to {}\r", ab_loop.a.display());
+            }
+            Command::LoopEnd => {
+                let mut ab_loop = ab_loop.borrow_mut();
+                ab_loop.b = pipeline.query_position::<gst::ClockTime>();
+                println!("Loop end set to {}\r", ab_loop.b.display());
+                ab_loop.start(&pipeline, rate);
+            }
+            Command::LoopClear => {
+                ab_loop.borrow_mut().stop(&pipeline);
+            }
+            Command::NextChapter | Command::PreviousChapter => {
+                let chapters = chapters.borrow();
+                if chapters.is_empty() {
+                    println!("This media has no chapters\r");
+                    return glib::Continue(true);
+                }
+
+                let position = pipeline
+                    .query_position::<gst::ClockTime>()
+                    .unwrap_or(gst::ClockTime::ZERO);
+                let chapter = if command == Command::NextChapter {
+                    chapters.next(position)
+                } else {
+                    chapters.previous(position)
+                };
+
+                match chapter {
+                    Some(chapter) => {
+                        println!(
+                            "Jumping to chapter '{}' at {}\r",
+                            chapter.title.as_deref().unwrap_or("(no title)"),
+                            chapter.start
+                        );
+                        // A plain seek leaves the loop and brings the rate back to 1
+                        ab_loop.borrow_mut().stop(&pipeline);
+                        let _ = pipeline
+                            .seek_simple(SeekFlags::FLUSH | SeekFlags::ACCURATE, chapter.start);
+                        rate = 1.;
+                    }
+                    None => println!("No chapter in that direction\r"),
+                }
+            }
             Command::Quit => {
                 main_loop_clone.quit();
             }
@@ -204,6 +304,7 @@ USAGE: Choose one of the following options, then press enter:
 
     main_loop.run();
 
+    bus.remove_watch()?;
     pipeline.set_state(State::Null)?;
 
     Ok(())
@@ -1,7 +1,13 @@
 use gst::prelude::*;
 
+use std::env;
+
 #[path = "../common.rs"]
 mod common;
+#[path = "../decoder_selection.rs"]
+mod decoder_selection;
+
+use decoder_selection::DecoderPolicy;
 
 //https://gstreamer.freedesktop.org/documentation/tutorials/basic/dynamic-pipelines.html?gi-language=c
 #[allow(dead_code)]
@@ -9,6 +15,10 @@ fn tutorial_main() {
     // Initialize GStreamer
     gst::init().unwrap();
 
+    // Adjust the decoder ranks (--prefer-decoder / --block-decoder) before anything gets autoplugged
+    let args: Vec<_> = env::args().collect();
+    DecoderPolicy::from_args(&args).apply();
+
     // The ports through which GStreamer elements communicate with each other are called pads (GstPad).
     // There exists sink pads, through which data enters an element, and source pads, through which data exits an element.
     // It follows naturally that source elements only contain source pads, sink elements only contain sink pads, and filter elements contain both.
@@ -20,6 +30,7 @@ fn tutorial_main() {
     // Since it contains demuxers, its source pads are not initially available and we will need to link to them on the fly.
     let source = gst::ElementFactory::make("uridecodebin", Some("source"))
         .expect("Could not create uridecodebin element.");
+    decoder_selection::log_chosen_decoders(source.downcast_ref::<gst::Bin>().unwrap());
 
     // audioconvert is useful for converting between different audio formats,
     // making sure that this example will work on any platform,
@@ -144,8 +155,13 @@ fn exercise() {
     // Initialize GStreamer
     gst::init().unwrap();
 
+    // Adjust the decoder ranks (--prefer-decoder / --block-decoder) before anything gets autoplugged
+    let args: Vec<_> = env::args().collect();
+    DecoderPolicy::from_args(&args).apply();
+
     let source = gst::ElementFactory::make("uridecodebin", Some("source"))
         .expect("Could not create uridecodebin element.");
+    decoder_selection::log_chosen_decoders(source.downcast_ref::<gst::Bin>().unwrap());
     let convert = gst::ElementFactory::make("videoconvert", Some("convert"))
         .expect("Could not create convert element.");
     let scale = gst::ElementFactory::make("videoscale", Some("scale"))
@@ -4,8 +4,41 @@ use std::io;
 #[allow(unused_imports)]
 use std::io::Write;
 
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[path = "../ab_loop.rs"]
+mod ab_loop;
+#[path = "../clock_time.rs"]
+mod clock_time;
 #[path = "../common.rs"]
 mod common;
+#[path = "../frame_seek.rs"]
+mod frame_seek;
+#[path = "../progress.rs"]
+mod progress;
+#[path = "../seek_bench.rs"]
+mod seek_bench;
+#[path = "../seek_script.rs"]
+mod seek_script;
+
+use ab_loop::AbLoop;
+use frame_seek::{FrameSeekResult, FrameTarget};
+use progress::ProgressReporter;
+use seek_script::ScriptRunner;
+
+// With the progress report on stdout, our own messages go to stderr so the two don't mix
+static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if STATUS_TO_STDERR.load(Ordering::Relaxed) {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
 
 struct CustomData {
     /// Our one and only element
@@ -20,12 +53,49 @@ struct CustomData {
     seek_done: bool,
     /// How long does this media last, in nanoseconds
     duration: Option<gst::ClockTime>,
+    /// Where to write the machine-readable progress, if requested
+    progress: Option<ProgressReporter>,
+    /// Section to loop instead of performing the seek, if requested
+    ab_loop: Option<AbLoop>,
+    /// Have we tried to start the loop already?
+    loop_started: bool,
 }
 
 fn tutorial_main() {
     // Initialize GStreamer
     gst::init().unwrap();
 
+    let args: Vec<_> = env::args().collect();
+    let progress = match ProgressReporter::from_args(&args).transpose() {
+        Ok(progress) => progress,
+        Err(err) => {
+            eprintln!("Failed to open the progress output: {}", err);
+            return;
+        }
+    };
+    let status_to_stderr = progress
+        .as_ref()
+        .map(|progress| progress.is_stdout())
+        .unwrap_or(false);
+    STATUS_TO_STDERR.store(status_to_stderr, Ordering::Relaxed);
+    let ab_loop = match AbLoop::from_args(&args).transpose() {
+        Ok(ab_loop) => ab_loop.map(|ab_loop| AbLoop {
+            log_to_stderr: status_to_stderr,
+            ..ab_loop
+        }),
+        Err(err) => {
+            eprintln!("Invalid loop: {}", err);
+            return;
+        }
+    };
+    let frame_target = match FrameTarget::from_args(&args).transpose() {
+        Ok(frame_target) => frame_target,
+        Err(err) => {
+            eprintln!("Invalid frame seek: {}", err);
+            return;
+        }
+    };
+
     // Creat the playbin element
     let playbin = gst::ElementFactory::make("playbin", Some("playbin"))
         .expect("Failed to create playbin element");
@@ -35,6 +105,33 @@ fn tutorial_main() {
         "https://www.freedesktop.org/software/gstreamer-sdk/data/media/sintel_trailer-480p.webm";
     playbin.set_property("uri", uri).unwrap();
 
+    // Run the timed actions of a seek script instead of the single seek below (--script <file>)
+    if let Some(path) = option_value(&args, "--script") {
+        run_seek_script(&playbin, path);
+        return;
+    }
+
+    // Measure the latency and accuracy of seeks with different flags instead of playing (--measure-seeks)
+    if args.iter().any(|arg| arg == "--measure-seeks") {
+        match seek_bench::measure_seeks(&playbin) {
+            Ok(measurements) => seek_bench::print_measurements(&measurements),
+            Err(err) => eprintln!("Seek measurement failed: {}", err),
+        }
+        playbin
+            .set_state(gst::State::Null)
+            .expect("Unable to set the playbin to the `Null` state");
+        return;
+    }
+
+    // Start from a given frame (--seek-frame <number> or --seek-timecode <HH:MM:SS:FF>),
+    // the seek below is then skipped
+    if let Some(target) = frame_target {
+        match frame_seek::seek_to_frame(&playbin, target) {
+            Ok(result) => print_frame_seek_result(&result),
+            Err(err) => eprintln!("Frame seek failed: {}", err),
+        }
+    }
+
     // Start playing
     playbin
         .set_state(gst::State::Playing)
@@ -47,8 +144,11 @@ fn tutorial_main() {
         playing: false,
         terminate: false,
         seek_enabled: false,
-        seek_done: false,
+        seek_done: frame_target.is_some(),
         duration: gst::ClockTime::NONE,
+        progress,
+        ab_loop,
+        loop_started: false,
     };
 
     while !custom_data.terminate {
@@ -59,6 +159,13 @@ fn tutorial_main() {
                 handle_message(&mut custom_data, &msg);
             }
             None => {
+                if let Some(progress) = custom_data.progress.as_mut() {
+                    if let Err(err) = progress.report(&custom_data.playbin) {
+                        eprintln!("Failed to write progress: {}", err);
+                        custom_data.progress = None;
+                    }
+                }
+
                 if custom_data.playing {
                     /* Query the current position of the stream */
                     let position = custom_data
@@ -81,12 +188,21 @@ fn tutorial_main() {
                     io::stdout().flush().unwrap();
                     */
 
+                    // In A-B loop mode, the loop replaces the seek below. It doesn't depend on
+                    // seek_done, which a seek to a frame sets before we get here.
+                    // It is started once: a failed segment seek isn't retried on every tick.
+                    if let Some(ab_loop) = custom_data.ab_loop.as_mut() {
+                        if custom_data.seek_enabled && !custom_data.loop_started {
+                            custom_data.loop_started = true;
+                            ab_loop.start(&custom_data.playbin, 1.0);
+                        }
+                    }
                     // /* If seeking is enabled, we have not done it yet, and the time is right, seek */
-                    if custom_data.seek_enabled
+                    else if custom_data.seek_enabled
                         && !custom_data.seek_done
                         && position > 10 * gst::ClockTime::SECOND
                     {
-                        println!("\nReached 10s, performing seek...");
+                        status!("\nReached 10s, performing seek...");
                         custom_data
                             .playbin
                             .seek_simple(
@@ -126,12 +242,68 @@ fn tutorial_main() {
         .expect("Unable to set the playbin to the `Null` state");
 }
 
+// Returns the argument following `name`, for options like "--script <file>"
+fn option_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|index| args.get(index + 1))
+        .map(|value| value.as_str())
+}
+
+fn print_frame_seek_result(result: &FrameSeekResult) {
+    status!(
+        "Seeked to frame {} ({}) at {}/{} fps",
+        result.requested_frame,
+        result.requested_time,
+        result.framerate.numer(),
+        result.framerate.denom()
+    );
+
+    match (result.actual_time, result.actual_frame()) {
+        (Some(time), Some(frame)) => status!(
+            "First buffer after the seek: frame {} ({}) -> {}",
+            frame,
+            time,
+            if result.is_exact() {
+                "exact"
+            } else {
+                "MISMATCH"
+            }
+        ),
+        _ => status!("No buffer reached the video sink after the seek"),
+    }
+}
+
+fn run_seek_script(playbin: &gst::Element, path: &str) {
+    let steps = match seek_script::load_script(path) {
+        Ok(steps) => steps,
+        Err(err) => {
+            eprintln!("Failed to load seek script: {}", err);
+            return;
+        }
+    };
+
+    let results = ScriptRunner::new(playbin).run(&steps);
+    seek_script::print_report(&results, steps.len());
+
+    playbin
+        .set_state(gst::State::Null)
+        .expect("Unable to set the playbin to the `Null` state");
+}
+
 fn handle_message(custom_data: &mut CustomData, msg: &gst::Message) {
     use gst::MessageView;
 
+    if let Some(progress) = custom_data.progress.as_mut() {
+        progress.handle_message(msg);
+    }
+    if let Some(ab_loop) = custom_data.ab_loop.as_mut() {
+        ab_loop.handle_message(&custom_data.playbin, msg);
+    }
+
     match msg.view() {
         MessageView::Error(err) => {
-            println!(
+            status!(
                 "Error received from element {:?}: {} ({:?})",
                 err.src().map(|s| s.path_string()),
                 err.error(),
@@ -140,7 +312,7 @@ fn handle_message(custom_data: &mut CustomData, msg: &gst::Message) {
             custom_data.terminate = true;
         }
         MessageView::Eos(..) => {
-            println!("End-Of-Stream reached.");
+            status!("End-Of-Stream reached.");
             custom_data.terminate = true;
         }
         MessageView::DurationChanged(_) => {
@@ -156,9 +328,10 @@ fn handle_message(custom_data: &mut CustomData, msg: &gst::Message) {
                 let new_state = state_changed.current();
                 let old_state = state_changed.old();
 
-                println!(
+                status!(
                     "Pipeline state changed from {:?} to {:?}",
-                    old_state, new_state
+                    old_state,
+                    new_state
                 );
 
                 custom_data.playing = new_state == gst::State::Playing;
@@ -174,9 +347,9 @@ fn handle_message(custom_data: &mut CustomData, msg: &gst::Message) {
                         let (seekable, start, end) = seeking.result();
                         custom_data.seek_enabled = seekable;
                         if seekable {
-                            println!("Seeking is ENABLED from {} to {}", start, end)
+                            status!("Seeking is ENABLED from {} to {}", start, end)
                         } else {
-                            println!("Seeking is DISABLED for this stream.")
+                            status!("Seeking is DISABLED for this stream.")
                         }
                     } else {
                         eprintln!("Seeking query failed.")
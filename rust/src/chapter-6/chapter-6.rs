@@ -1,31 +1,24 @@
 use gst::prelude::*;
 
+use std::env;
+
+#[path = "../caps_compat.rs"]
+mod caps_compat;
+#[path = "../caps_constraints.rs"]
+mod caps_constraints;
+#[path = "../caps_tracer.rs"]
+mod caps_tracer;
 #[path = "../common.rs"]
 mod common;
+#[path = "../element_info.rs"]
+mod element_info;
+
+use caps_constraints::CapsConstraint;
+use caps_tracer::CapsTracer;
 
 fn print_caps(caps: &gst::Caps, prefix: &str) {
     println!("===== print_caps =====");
-    if caps.is_any() {
-        println!("{}ANY", prefix);
-        return;
-    }
-
-    if caps.is_empty() {
-        println!("{}EMPTY", prefix);
-        return;
-    }
-
-    for structure in caps.iter() {
-        println!("{}{}", prefix, structure.name());
-        for (field, value) in structure.iter() {
-            println!(
-                "{}  {}:{}",
-                prefix,
-                field,
-                value.serialize().unwrap().as_str()
-            );
-        }
-    }
+    element_info::print_caps(caps, prefix);
 }
 
 // Prints information about a Pad Template, including its Capabilitites
@@ -59,29 +52,7 @@ fn print_pad_template_information(factory: &gst::ElementFactory) {
     // Pad Templates can be viewed as the first step in the negotiation process.
     // As the process evolves, actual Pads are instantiated and their Capabilities refined until they are fixed (or negotiation fails).
 
-    for pad_template in factory.static_pad_templates() {
-        if pad_template.direction() == gst::PadDirection::Src {
-            println!("  SRC template: '{}'", pad_template.name_template());
-        } else if pad_template.direction() == gst::PadDirection::Sink {
-            println!("  SINK template: '{}'", pad_template.name_template());
-        } else {
-            println!("  UNKNOWN!!! template: '{}'", pad_template.name_template());
-        }
-
-        if pad_template.presence() == gst::PadPresence::Always {
-            println!("  Availability: Always");
-        } else if pad_template.presence() == gst::PadPresence::Sometimes {
-            println!("  Availability: Sometimes");
-        } else if pad_template.presence() == gst::PadPresence::Request {
-            println!("  Availability: On request");
-        } else {
-            println!("  Availability: UNKNOWN!!!");
-        }
-
-        let caps = pad_template.caps();
-        println!("  Capabilities:");
-        print_caps(&caps, "    ");
-    }
+    element_info::print_pad_templates(factory, "  ");
 }
 
 fn print_pad_capabilities(element: &gst::Element, pad_name: &str) {
@@ -124,6 +95,29 @@ fn tutorial_main() {
     pipeline.add_many(&[&source, &sink]).unwrap();
     source.link(&sink).expect("Elements could not be linked.");
 
+    // Force formats on links with capsfilters (--caps <upstream>:<downstream>=<caps>)
+    let args: Vec<_> = env::args().collect();
+    let constraints = match CapsConstraint::from_args(&args) {
+        Ok(constraints) => constraints,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+    if let Err(err) = caps_constraints::apply(pipeline.upcast_ref(), &constraints) {
+        eprintln!("Failed to apply the caps constraints: {}", err);
+        return;
+    }
+
+    // Record every caps event, caps query and accept-caps query (--trace-caps)
+    let tracer = if args.iter().any(|arg| arg == "--trace-caps") {
+        let tracer = CapsTracer::new();
+        tracer.attach(pipeline.upcast_ref());
+        Some(tracer)
+    } else {
+        None
+    };
+
     // Print initial negotiated caps (in NULL state)
     println!("In NULL state:");
     print_pad_capabilities(&sink, "sink");
@@ -138,6 +132,7 @@ fn tutorial_main() {
 
     // Wait until error, EOS or State Change
     let bus = pipeline.bus().unwrap();
+    let mut trace_printed = false;
 
     for msg in bus.iter_timed(gst::ClockTime::NONE) {
         use gst::MessageView;
@@ -150,6 +145,9 @@ fn tutorial_main() {
                     err.error(),
                     err.debug()
                 );
+                if !constraints.is_empty() {
+                    caps_constraints::print_report(pipeline.upcast_ref(), &constraints);
+                }
                 break;
             }
             MessageView::Eos(..) => {
@@ -168,6 +166,23 @@ fn tutorial_main() {
                         old_state, new_state
                     );
                     print_pad_capabilities(&sink, "sink");
+
+                    // Once prerolled, every link has negotiated its caps
+                    if old_state == gst::State::Ready
+                        && new_state == gst::State::Paused
+                        && !constraints.is_empty()
+                    {
+                        caps_constraints::print_report(pipeline.upcast_ref(), &constraints);
+                    }
+
+                    // audiotestsrc never ends, so show the trace as soon as the data flows
+                    if old_state == gst::State::Paused && new_state == gst::State::Playing {
+                        if let Some(tracer) = tracer.as_ref() {
+                            tracer.print_timeline();
+                            tracer.print_rejections();
+                            trace_printed = true;
+                        }
+                    }
                 }
             }
             _ => (),
@@ -178,6 +193,12 @@ fn tutorial_main() {
     pipeline
         .set_state(gst::State::Null)
         .expect("Unable to set the pipeline to the `Null` state");
+
+    // Otherwise the pipeline stopped before PLAYING, show what was negotiated until then
+    if let Some(tracer) = tracer.filter(|_| !trace_printed) {
+        tracer.print_timeline();
+        tracer.print_rejections();
+    }
 }
 
 fn main() {
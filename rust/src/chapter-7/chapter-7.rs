@@ -1,7 +1,114 @@
 use gst::prelude::*;
 
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+#[path = "../clock_time.rs"]
+mod clock_time;
 #[path = "../common.rs"]
 mod common;
+#[path = "../queue_stats.rs"]
+mod queue_stats;
+#[path = "../tee_branch.rs"]
+mod tee_branch;
+#[path = "../visualizer.rs"]
+mod visualizer;
+
+use queue_stats::{QueuePolicy, QueueStats};
+use tee_branch::BranchManager;
+use visualizer::VisualizerConfig;
+
+// With "--record <file.ogg>", a recording branch is added to the running tee after RECORD_START
+// and removed RECORD_LENGTH later, without the audio and video branches noticing
+const RECORD_START: Duration = Duration::from_secs(3);
+const RECORD_LENGTH: Duration = Duration::from_secs(10);
+
+fn recording_branch(location: &str) -> Result<Vec<gst::Element>, glib::BoolError> {
+    let queue = gst::ElementFactory::make("queue", Some("record_queue"))?;
+    let convert = gst::ElementFactory::make("audioconvert", Some("record_convert"))?;
+    let encoder = gst::ElementFactory::make("vorbisenc", Some("record_encoder"))?;
+    let muxer = gst::ElementFactory::make("oggmux", Some("record_muxer"))?;
+    let sink = gst::ElementFactory::make("filesink", Some("record_sink"))?;
+    sink.set_property("location", location)?;
+
+    Ok(vec![queue, convert, encoder, muxer, sink])
+}
+
+// Starts and stops the recording from another thread while the main one watches the bus
+fn record_later(branches: BranchManager, location: String) {
+    thread::spawn(move || {
+        thread::sleep(RECORD_START);
+        let elements = match recording_branch(&location) {
+            Ok(elements) => elements,
+            Err(err) => {
+                eprintln!("Failed to create the recording branch: {}", err);
+                return;
+            }
+        };
+        let elements: Vec<_> = elements.iter().collect();
+        if let Err(err) = branches.add_branch("record", &elements) {
+            eprintln!("Failed to start recording: {}", err);
+            return;
+        }
+        println!("Recording to {}", location);
+
+        thread::sleep(RECORD_LENGTH);
+        // The EOS lets oggmux finish the file before the branch goes away
+        if let Err(err) = branches.remove_branch("record") {
+            eprintln!("Failed to stop recording: {}", err);
+        }
+    });
+}
+
+// With "--visual-cycle <seconds>", the visualizer is replaced by the next available one every
+// few seconds while the pipeline keeps playing. The one given with "--visual" keeps its properties.
+fn cycle_visualizers(
+    pipeline: gst::Pipeline,
+    branches: BranchManager,
+    mut visual: gst::Element,
+    config: VisualizerConfig,
+    seconds: u64,
+) {
+    let names = visualizer::available();
+    println!("Cycling through {}", names.join(", "));
+
+    let configs: Vec<VisualizerConfig> = names
+        .iter()
+        .map(|name| {
+            if *name == config.factory {
+                config.clone()
+            } else {
+                VisualizerConfig::parse(name).unwrap()
+            }
+        })
+        .collect();
+
+    // Start with the one after the visualizer we have
+    let current = names
+        .iter()
+        .position(|name| *name == config.factory)
+        .map(|position| position + 1)
+        .unwrap_or(0);
+
+    thread::spawn(move || {
+        for config in configs.iter().cycle().skip(current) {
+            thread::sleep(Duration::from_secs(seconds));
+
+            match visualizer::swap(&pipeline, &visual, config) {
+                Ok(new) => {
+                    println!("Now showing {}", config.factory);
+                    branches.replace_element(&visual, &new);
+                    visual = new;
+                }
+                Err(err) => {
+                    eprintln!("Failed to switch to {}: {}", config.factory, err);
+                    return;
+                }
+            }
+        }
+    });
+}
 
 fn tutorial_main() {
     // Initialize GStreamer
@@ -19,7 +126,7 @@ fn tutorial_main() {
     // In this way, an input stream can be replicated any number of times.
     // The disadvantage is that linking elements with Request Pads is not as automatic, as linking Always Pads, as the walkthrough for this example will show.
     // Also, to request (or release) pads in the PLAYING or PAUSED states,
-    // you need to take additional cautions (Pad blocking) which are not described in this tutorial.
+    // you need to take additional cautions (Pad blocking), which tee_branch.rs takes care of for the recording branch.
     // It is safe to request (or release) pads in the NULL or READY states, though.
 
     let audio_source = gst::ElementFactory::make("audiotestsrc", Some("audio_source")).unwrap();
@@ -30,15 +137,28 @@ fn tutorial_main() {
         gst::ElementFactory::make("audioresample", Some("audio_resample")).unwrap();
     let audio_sink = gst::ElementFactory::make("autoaudiosink", Some("audio_sink")).unwrap();
     let video_queue = gst::ElementFactory::make("queue", Some("video_queue")).unwrap();
-    let visual = gst::ElementFactory::make("wavescope", Some("visual")).unwrap();
+    let args: Vec<_> = env::args().collect();
+    // wavescope with shader=none and style=lines, unless "--visual" says otherwise
+    let visual_config = match VisualizerConfig::from_args(&args) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+    let visual = match visual_config.make("visual") {
+        Ok(visual) => visual,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
     let video_convert = gst::ElementFactory::make("videoconvert", Some("video_convert")).unwrap();
     let video_sink = gst::ElementFactory::make("autovideosink", Some("video_sink")).unwrap();
 
     let pipeline = gst::Pipeline::new(Some("test-pipeline"));
 
     audio_source.set_property("freq", 215.0).unwrap();
-    visual.set_property_from_str("shader", "none");
-    visual.set_property_from_str("style", "lines");
 
     pipeline
         .add_many(&[
@@ -75,15 +195,104 @@ fn tutorial_main() {
     let queue_video_pad = video_queue.static_pad("sink").unwrap();
     tee_video_pad.link(&queue_video_pad).unwrap();
 
-    pipeline
-        .set_state(gst::State::Playing)
-        .expect("Unable to set the pipeline to the `Playing` state");
+    let branches = BranchManager::new(&pipeline, &tee);
+    branches.register_branch(
+        "audio",
+        &tee_audio_pad,
+        &[&audio_queue, &audio_convert, &audio_resample, &audio_sink],
+    );
+    branches.register_branch(
+        "video",
+        &tee_video_pad,
+        &[&video_queue, &visual, &video_convert, &video_sink],
+    );
+
+    if let Some(i) = args.iter().position(|arg| arg == "--record") {
+        match args.get(i + 1) {
+            Some(location) => record_later(branches.clone(), location.clone()),
+            None => eprintln!("--record needs a file name"),
+        }
+    }
+
+    if let Some(i) = args.iter().position(|arg| arg == "--visual-cycle") {
+        match args.get(i + 1).and_then(|seconds| seconds.parse().ok()) {
+            Some(seconds) => cycle_visualizers(
+                pipeline.clone(),
+                branches.clone(),
+                visual.clone(),
+                visual_config.clone(),
+                seconds,
+            ),
+            None => eprintln!("--visual-cycle needs a number of seconds"),
+        }
+    }
+
+    // Limits and leakiness of the branch queues, so a slow branch can't stall the tee
+    let policies = match QueuePolicy::from_args(&args) {
+        Ok(policies) => policies,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+    if let Err(err) = queue_stats::apply_policies(pipeline.upcast_ref(), &policies) {
+        eprintln!("Failed to apply the queue policies: {}", err);
+        return;
+    }
+
+    if let Some(interval) = queue_stats::report_interval(&args) {
+        let stats = QueueStats::new();
+        stats
+            .watch_all(pipeline.upcast_ref())
+            .expect("Failed to watch the queues");
+        // The main thread is busy waiting on the bus, the reports come from another one
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(u64::from(interval)));
+            stats.report();
+        });
+    }
+
+    // An error inside a branch should only take that branch down. The sync handler runs in the
+    // thread posting the error, so the branch is usually cut off before the error flows back to
+    // the tee. This is best effort: if the tee still gets it, the pipeline stops with the error.
     let bus = pipeline.bus().unwrap();
+    let branches_clone = branches.clone();
+    bus.set_sync_handler(move |_, msg| {
+        if let gst::MessageView::Error(err) = msg.view() {
+            if let Some(name) = err.src().and_then(|src| branches_clone.branch_of(&src)) {
+                branches_clone.isolate(&name);
+            }
+        }
+        gst::BusSyncReply::Pass
+    });
+
+    // A sink that can't be opened makes this fail, its branch is dropped below and we try again
+    if pipeline.set_state(gst::State::Playing).is_err() {
+        eprintln!("Unable to set the pipeline to the `Playing` state");
+    }
     for msg in bus.iter_timed(gst::ClockTime::NONE) {
         use gst::MessageView;
 
         match msg.view() {
             MessageView::Error(err) => {
+                let branch = err.src().and_then(|src| branches.branch_of(&src));
+                if let Some(name) = branch {
+                    eprintln!(
+                        "Branch {} failed, removing it: {} ({:?})",
+                        name,
+                        err.error(),
+                        err.src().map(|s| s.path_string())
+                    );
+                    if let Err(err) = branches.drop_branch(&name) {
+                        eprintln!("Failed to remove branch {}: {}", name, err);
+                        break;
+                    }
+                    if pipeline.current_state() != gst::State::Playing {
+                        let _ = pipeline.set_state(gst::State::Playing);
+                    }
+                    continue;
+                }
+
                 eprintln!(
                     "Error received from element {:?}: {}",
                     err.src().map(|s| s.path_string()),
@@ -100,6 +309,7 @@ fn tutorial_main() {
     pipeline
         .set_state(gst::State::Null)
         .expect("Unable to set the pipeline to the `Null` state");
+    bus.unset_sync_handler();
 }
 
 fn main() {
@@ -0,0 +1,205 @@
+use std::env;
+use std::io::{self, Write};
+
+use futures::prelude::*;
+use gst::prelude::*;
+use gst_app::{AppSink, AppSrc};
+
+#[path = "../app_async.rs"]
+mod app_async;
+#[path = "../audio_analysis.rs"]
+mod audio_analysis;
+#[path = "../audio_config.rs"]
+mod audio_config;
+#[path = "../clock_time.rs"]
+mod clock_time;
+#[path = "../waveform.rs"]
+mod waveform;
+
+use app_async::{BufferSink, SampleStream};
+use audio_config::AudioConfig;
+
+// chapter-8 on top of futures instead of callbacks.
+// The same pipeline: appsrc ! tee, with an audio branch, a wavescope branch and an appsink.
+// Three tasks run on the glib main context, no thread of ours and no shared state:
+//  - the producer generates buffers and sends them into the appsrc, waiting while it is full
+//  - the consumer takes the samples out of the appsink and prints a * (or levels, with "--analyze")
+//  - the bus watch ends everything on an error or at the end of the stream
+// The options are those of chapter-8 for the format, "--wave" and "--duration".
+
+const MAX_BUFFERS: u64 = 16; // Most buffers waiting in the appsrc
+
+async fn produce(
+    mut sink: BufferSink,
+    config: AudioConfig,
+    mut generator: Box<dyn waveform::WaveformGenerator>,
+    duration: Option<gst::ClockTime>,
+) {
+    let info = config.info().expect("Invalid audio format");
+    let num_samples = config.samples_per_buffer();
+    let buffer_size = num_samples as usize * info.bpf() as usize;
+    let mut position = 0;
+
+    loop {
+        // Each duration is the distance to the next timestamp, so no rounding error accumulates
+        let pts = audio_config::samples_to_time(position, info.rate());
+        if duration.map(|duration| pts >= duration).unwrap_or(false) {
+            break;
+        }
+        let next = audio_config::samples_to_time(position + num_samples, info.rate());
+        position += num_samples;
+
+        let mut samples = vec![0; buffer_size];
+        generator.fill(&info, &mut samples);
+        let mut buffer = gst::Buffer::from_mut_slice(samples);
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(pts);
+            buffer.set_duration(next - pts);
+        }
+
+        // Waits for need-data when the appsrc is full
+        if let Err(err) = sink.send(buffer).await {
+            if err != gst::FlowError::Flushing {
+                eprintln!("Failed to push a buffer: {:?}", err);
+            }
+            return;
+        }
+    }
+
+    // EOS
+    let _ = sink.close().await;
+}
+
+async fn consume(mut samples: SampleStream, analyze: bool) {
+    while let Some(sample) = samples.next().await {
+        if analyze {
+            let info = sample
+                .caps()
+                .and_then(|caps| gst_audio::AudioInfo::from_caps(caps).ok());
+            if let (Some(info), Some(buffer)) = (info, sample.buffer()) {
+                if let Ok(map) = buffer.map_readable() {
+                    audio_analysis::analyze(&info, &map).print(buffer.pts());
+                }
+            }
+        } else {
+            print!("*");
+            let _ = io::stdout().flush();
+        }
+    }
+}
+
+async fn watch_bus(bus: gst::Bus) {
+    let mut messages = bus.stream();
+    while let Some(msg) = messages.next().await {
+        match msg.view() {
+            gst::MessageView::Error(err) => {
+                eprintln!(
+                    "Error received from element {:?}: {}",
+                    err.src().map(|s| s.path_string()),
+                    err.error()
+                );
+                eprintln!("Debugging information: {:?}", err.debug());
+                break;
+            }
+            gst::MessageView::Eos(..) => {
+                println!("\nEnd of stream");
+                break;
+            }
+            _ => (),
+        }
+    }
+}
+
+fn main() {
+    // Initialize GStreamer
+    if let Err(err) = gst::init() {
+        eprintln!("Failed to initialize Gst: {}", err);
+        return;
+    }
+
+    let args: Vec<_> = env::args().collect();
+    let config = match AudioConfig::from_args(&args) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+    let generator = match waveform::from_args(&args, config.rate) {
+        Ok(generator) => generator,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+    let duration = match args.iter().position(|arg| arg == "--duration") {
+        Some(i) => match args
+            .get(i + 1)
+            .and_then(|time| clock_time::parse_time(time))
+        {
+            Some(duration) => Some(duration),
+            None => {
+                eprintln!("--duration needs a time");
+                return;
+            }
+        },
+        None => None,
+    };
+    let analyze = args.iter().any(|arg| arg == "--analyze");
+
+    let pipeline = gst::parse_launch(
+        "appsrc name=audio_source format=time ! tee name=tee \
+         tee. ! queue ! audioconvert ! audioresample ! autoaudiosink \
+         tee. ! queue ! audioconvert ! wavescope shader=none style=lines ! videoconvert ! autovideosink \
+         tee. ! queue ! appsink name=app_sink",
+    )
+    .expect("Failed to build the pipeline")
+    .downcast::<gst::Pipeline>()
+    .unwrap();
+
+    let info = config.info().expect("Invalid audio format");
+    let audio_caps = info.to_caps().unwrap();
+
+    let appsrc = pipeline
+        .by_name("audio_source")
+        .unwrap()
+        .dynamic_cast::<AppSrc>()
+        .expect("Source element is expected to be an appsrc!");
+    appsrc.set_caps(Some(&audio_caps));
+    let appsink = pipeline
+        .by_name("app_sink")
+        .unwrap()
+        .dynamic_cast::<AppSink>()
+        .expect("Sink element is expected to be an appsink!");
+    appsink.set_caps(Some(&audio_caps));
+
+    let max_bytes = MAX_BUFFERS * config.samples_per_buffer() * u64::from(info.bpf());
+    let sink = BufferSink::new(&appsrc, max_bytes);
+    let samples = SampleStream::new(&appsink);
+    let bus = pipeline.bus().unwrap();
+
+    let tasks = future::join(
+        produce(sink, config, generator, duration),
+        consume(samples, analyze),
+    );
+    let watch = watch_bus(bus);
+
+    // Runs the main context until everything is done
+    glib::MainContext::default().block_on(async {
+        pipeline
+            .set_state(gst::State::Playing)
+            .expect("Unable to set the pipeline to the `Playing` state.");
+
+        // The bus has the last word: after the producer and the consumer are done, the other
+        // sinks may still be playing, and on an error the tasks are dropped unfinished
+        futures::pin_mut!(tasks, watch);
+        if let future::Either::Left((_, watch)) = future::select(tasks, watch).await {
+            watch.await;
+        }
+    });
+
+    pipeline
+        .set_state(gst::State::Null)
+        .expect("Unable to set the pipeline to the `Null` state.");
+}
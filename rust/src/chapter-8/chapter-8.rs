@@ -1,45 +1,103 @@
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
 use std::sync::{Arc, Mutex};
 
-use byte_slice_cast::*;
-
-use glib::source::SourceId;
 use gst::prelude::*;
 use gst_app::{AppSink, AppSrc};
 use gst_audio::AudioInfo;
 
-const CHUNK_SIZE: usize = 1024; // Amount of bytes we are sending in each buffer
-const SAMPLE_RATE: u32 = 44_100; // Samples per second we are sending
+#[path = "../audio_analysis.rs"]
+mod audio_analysis;
+#[path = "../audio_config.rs"]
+mod audio_config;
+#[path = "../clock_time.rs"]
+mod clock_time;
+#[path = "../feeder.rs"]
+mod feeder;
+#[path = "../file_source.rs"]
+mod file_source;
+#[path = "../frame_seek.rs"]
+mod frame_seek;
+#[path = "../queue_stats.rs"]
+mod queue_stats;
+#[path = "../video_frames.rs"]
+mod video_frames;
+#[path = "../wav.rs"]
+mod wav;
+#[path = "../waveform.rs"]
+mod waveform;
+
+use audio_config::AudioConfig;
+use feeder::Feeder;
+use file_source::FileSource;
+use queue_stats::{QueuePolicy, QueueStats};
+use video_frames::{FrameGenerator, VideoConfig};
+use wav::WavWriter;
+use waveform::WaveformGenerator;
+
+const MAX_BUFFERS: u64 = 16; // Most buffers waiting in the appsrc
+const SEEK_DELAY: u32 = 3; // Seconds of playback before "--seek"
 
 #[derive(Debug)]
 struct CustomData {
-    source_id: Option<SourceId>,
-
     num_samples: u64, // Number of samples generated so far (for timestamp generation)
-    // For waveform generation
-    a: f64,
-    b: f64,
-    c: f64,
-    d: f64,
+    info: AudioInfo,  // Format of the samples we generate
+    generator: Box<dyn WaveformGenerator>,
 
-    appsrc: AppSrc,
     appsink: AppSink,
+    wav_path: Option<String>, // Where "--wav" captures what the appsink receives
+    wav: Option<WavWriter<BufWriter<File>>>, // Created with the caps of the first sample
 }
 
 impl CustomData {
-    fn new(appsrc: &AppSrc, appsink: &AppSink) -> CustomData {
+    fn new(
+        appsink: &AppSink,
+        info: &AudioInfo,
+        generator: Box<dyn WaveformGenerator>,
+    ) -> CustomData {
         CustomData {
-            source_id: None,
             num_samples: 0,
-            a: 0.0,
-            b: 1.0,
-            c: 0.0,
-            d: 1.0,
-            appsrc: appsrc.clone(),
+            info: info.clone(),
+            generator,
             appsink: appsink.clone(),
+            wav_path: None,
+            wav: None,
         }
     }
 }
 
+// Levels, zero-crossing rate and spectrum of the samples, in the format of their caps
+fn analyze_sample(sample: &gst::Sample) -> Option<audio_analysis::Analysis> {
+    let info = AudioInfo::from_caps(sample.caps()?).ok()?;
+    let buffer = sample.buffer()?.map_readable().ok()?;
+    Some(audio_analysis::analyze(&info, &buffer))
+}
+
+// With "--wav", appends the samples to the WAV file, which is created for the format of the first ones
+fn capture_sample(data: &mut CustomData, sample: &gst::Sample) -> std::io::Result<()> {
+    let path = match data.wav_path {
+        Some(ref path) => path,
+        None => return Ok(()),
+    };
+
+    if data.wav.is_none() {
+        let info = sample
+            .caps()
+            .and_then(|caps| AudioInfo::from_caps(caps).ok())
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "no audio caps"))?;
+        data.wav = Some(WavWriter::create(path, &info)?);
+    }
+
+    if let (Some(wav), Some(buffer)) = (data.wav.as_mut(), sample.buffer()) {
+        let map = buffer
+            .map_readable()
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "unreadable buffer"))?;
+        wav.write(&map)?;
+    }
+    Ok(())
+}
+
 fn main() {
     // Initialize GStreamer
     if let Err(err) = gst::init() {
@@ -64,6 +122,11 @@ fn main() {
 
     let pipeline = gst::Pipeline::new(Some("test-pipeline"));
 
+    // With "--video", the appsrc produces frames instead of samples: there is no audio branch,
+    // and the video branch shows the frames instead of a visualization of the samples
+    let args: Vec<_> = env::args().collect();
+    let video = args.iter().any(|arg| arg == "--video");
+
     visual.set_property_from_str("shader", "none");
     visual.set_property_from_str("style", "lines");
 
@@ -71,40 +134,52 @@ fn main() {
         .add_many(&[
             &appsrc,
             &tee,
-            &audio_queue,
-            &audio_convert1,
-            &audio_resample,
-            &audio_sink,
             &video_queue,
-            &audio_convert2,
-            &visual,
             &video_convert,
             &video_sink,
             &app_queue,
             &appsink,
         ])
         .unwrap();
+    if !video {
+        pipeline
+            .add_many(&[
+                &audio_queue,
+                &audio_convert1,
+                &audio_resample,
+                &audio_sink,
+                &audio_convert2,
+                &visual,
+            ])
+            .unwrap();
+    }
 
     gst::Element::link_many(&[&appsrc, &tee]).unwrap();
-    gst::Element::link_many(&[&audio_queue, &audio_convert1, &audio_resample, &audio_sink])
+    if video {
+        gst::Element::link_many(&[&video_queue, &video_convert, &video_sink]).unwrap();
+    } else {
+        gst::Element::link_many(&[&audio_queue, &audio_convert1, &audio_resample, &audio_sink])
+            .unwrap();
+        gst::Element::link_many(&[
+            &video_queue,
+            &audio_convert2,
+            &visual,
+            &video_convert,
+            &video_sink,
+        ])
         .unwrap();
-    gst::Element::link_many(&[
-        &video_queue,
-        &audio_convert2,
-        &visual,
-        &video_convert,
-        &video_sink,
-    ])
-    .unwrap();
+    }
     gst::Element::link_many(&[&app_queue, &appsink]).unwrap();
 
-    let tee_audio_pad = tee.request_pad_simple("src_%u").unwrap();
-    println!(
-        "Obtained request pad {} for audio branch",
-        tee_audio_pad.name()
-    );
-    let queue_audio_pad = audio_queue.static_pad("sink").unwrap();
-    tee_audio_pad.link(&queue_audio_pad).unwrap();
+    if !video {
+        let tee_audio_pad = tee.request_pad_simple("src_%u").unwrap();
+        println!(
+            "Obtained request pad {} for audio branch",
+            tee_audio_pad.name()
+        );
+        let queue_audio_pad = audio_queue.static_pad("sink").unwrap();
+        tee_audio_pad.link(&queue_audio_pad).unwrap();
+    }
 
     let tee_video_pad = tee.request_pad_simple("src_%u").unwrap();
     println!(
@@ -117,6 +192,19 @@ fn main() {
     let queue_app_pad = app_queue.static_pad("sink").unwrap();
     tee_app_pad.link(&queue_app_pad).unwrap();
 
+    // Limits and leakiness of the branch queues, so a slow branch can't stall the tee
+    let policies = match QueuePolicy::from_args(&args) {
+        Ok(policies) => policies,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+    if let Err(err) = queue_stats::apply_policies(pipeline.upcast_ref(), &policies) {
+        eprintln!("Failed to apply the queue policies: {}", err);
+        return;
+    }
+
     // configure appsrc
 
     // The first property that needs to be set on the appsrc is caps.
@@ -128,16 +216,89 @@ fn main() {
     // We then connect to the need-data and enough-data signals.
     // These are fired by appsrc when its internal queue of data is running low or almost full, respectively.
     // We will use these signals to start and stop (respectively) our signal generation process.
-
-    let info = AudioInfo::builder(gst_audio::AudioFormat::S16le, SAMPLE_RATE, 1)
-        .build()
-        .unwrap();
-    let audio_caps = info.to_caps().unwrap();
+    // The feeder (feeder.rs) does that on a thread of its own, bounded by the max-bytes property.
+
+    // Mono S16LE at 44100 Hz unless "--format", "--channels", "--rate" or "--buffer-duration" say otherwise
+    let config = match AudioConfig::from_args(&args) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+
+    // With "--file <path>", the samples come from a WAV file, or a raw one in the format above
+    let file_source = match args.iter().position(|arg| arg == "--file") {
+        Some(i) => match args.get(i + 1).map(|path| FileSource::open(path, &config)) {
+            Some(Ok(source)) => Some(source),
+            Some(Err(err)) => {
+                eprintln!("Failed to open the file: {}", err);
+                return;
+            }
+            None => {
+                eprintln!("--file needs a file name");
+                return;
+            }
+        },
+        None => None,
+    };
+
+    // 640x480 RGBA at 30 fps unless "--video-format", "--size" or "--framerate" say otherwise
+    let video_info = if video {
+        if args
+            .iter()
+            .any(|arg| arg == "--file" || arg == "--wav" || arg == "--analyze")
+        {
+            eprintln!("--file, --wav and --analyze only work with audio");
+            return;
+        }
+        match VideoConfig::from_args(&args) {
+            Ok(config) => Some(config.info().expect("Invalid video format")),
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            }
+        }
+    } else {
+        None
+    };
+
+    let info = match file_source {
+        Some(ref source) => source.info().clone(),
+        None => config.info().expect("Invalid audio format"),
+    };
+    let caps = match video_info {
+        Some(ref video_info) => {
+            println!(
+                "Generating {}x{} frames of {} at {}/{} fps",
+                video_info.width(),
+                video_info.height(),
+                video_info.format().to_str(),
+                video_info.fps().numer(),
+                video_info.fps().denom()
+            );
+            video_info.to_caps().unwrap()
+        }
+        None => {
+            println!(
+                "{} {} channels of {} at {} Hz",
+                if file_source.is_some() {
+                    "Reading"
+                } else {
+                    "Generating"
+                },
+                info.channels(),
+                info.format().to_str(),
+                info.rate()
+            );
+            info.to_caps().unwrap()
+        }
+    };
 
     let appsrc = appsrc
         .dynamic_cast::<AppSrc>()
         .expect("Source element is expected to be an appsrc!");
-    appsrc.set_caps(Some(&audio_caps));
+    appsrc.set_caps(Some(&caps));
     appsrc.set_format(gst::Format::Time);
 
     // Regarding the appsink configuration, we connect to the new-sample signal,
@@ -148,90 +309,131 @@ fn main() {
         .dynamic_cast::<AppSink>()
         .expect("Sink element is expected to be an appsink!");
 
-    let data: Arc<Mutex<CustomData>> = Arc::new(Mutex::new(CustomData::new(&appsrc, &appsink)));
+    // The tutorial's psychedelic waveform, unless "--wave" picks another one
+    let generator = match waveform::from_args(&args, config.rate) {
+        Ok(generator) => generator,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+
+    let data: Arc<Mutex<CustomData>> =
+        Arc::new(Mutex::new(CustomData::new(&appsink, &info, generator)));
+
+    // Stop after "--duration <time>" if given, with an EOS
+    let duration = match args.iter().position(|arg| arg == "--duration") {
+        Some(i) => match args
+            .get(i + 1)
+            .and_then(|time| clock_time::parse_time(time))
+        {
+            Some(duration) => Some(duration),
+            None => {
+                eprintln!("--duration needs a time");
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let data_clone = data.clone();
+    let num_samples = config.samples_per_buffer();
+    let buffer_size = num_samples as usize * info.bpf() as usize;
+    let max_bytes = MAX_BUFFERS * buffer_size as u64;
+    let generate = move || {
+        let mut data = data_clone.lock().unwrap();
+        let mut buffer = gst::Buffer::with_size(buffer_size).unwrap();
+        let rate = data.info.rate();
+        // Each duration is the distance to the next timestamp, so no rounding error accumulates
+        let pts = audio_config::samples_to_time(data.num_samples, rate);
+        let duration_buffer =
+            audio_config::samples_to_time(data.num_samples + num_samples, rate) - pts;
+
+        if duration.map(|duration| pts >= duration).unwrap_or(false) {
+            return None;
+        }
 
-    let data_weak = Arc::downgrade(&data);
-    let data_weak2 = Arc::downgrade(&data);
-    appsrc.set_callbacks(
-        gst_app::AppSrcCallbacks::builder()
-            .need_data(move |_, _size| {
-                let data = match data_weak.upgrade() {
-                    Some(data) => data,
-                    None => return,
-                };
-                let mut d = data.lock().unwrap();
-
-                if d.source_id.is_none() {
-                    println!("start feeding");
-
-                    let data_weak = Arc::downgrade(&data);
-                    d.source_id = Some(glib::source::idle_add(move || {
-                        let data = match data_weak.upgrade() {
-                            Some(data) => data,
-                            None => return glib::Continue(false),
-                        };
-
-                        let (appsrc, buffer) = {
-                            let mut data = data.lock().unwrap();
-                            let mut buffer = gst::Buffer::with_size(CHUNK_SIZE).unwrap();
-                            let num_samples = CHUNK_SIZE / 2; /* Each sample is 16 bits */
-                            let pts = gst::ClockTime::SECOND
-                                .mul_div_floor(data.num_samples, u64::from(SAMPLE_RATE))
-                                .expect("u64 overflow");
-                            let duration = gst::ClockTime::SECOND
-                                .mul_div_floor(num_samples as u64, u64::from(SAMPLE_RATE))
-                                .expect("u64 overflow");
-
-                            {
-                                let buffer = buffer.get_mut().unwrap();
-                                {
-                                    let mut samples = buffer.map_writable().unwrap();
-                                    let samples = samples.as_mut_slice_of::<i16>().unwrap();
-
-                                    // Generate some psychodelic waveforms
-                                    data.c += data.d;
-                                    data.d -= data.c / 1000.0;
-                                    let freq = 1100.0 + 1000.0 * data.d;
-
-                                    for sample in samples.iter_mut() {
-                                        data.a += data.b;
-                                        data.b -= data.a / freq;
-                                        *sample = 500 * (data.a as i16);
-                                        //*sample = 0;
-                                    }
-
-                                    data.num_samples += num_samples as u64;
-                                }
-
-                                buffer.set_pts(pts);
-                                buffer.set_duration(duration);
-                            }
-
-                            (data.appsrc.clone(), buffer)
-                        };
-
-                        glib::Continue(appsrc.push_buffer(buffer).is_ok())
-                    }));
-                }
-            })
-            .enough_data(move |_| {
-                let data = match data_weak2.upgrade() {
-                    Some(data) => data,
-                    None => return,
-                };
+        {
+            let buffer = buffer.get_mut().unwrap();
+            {
+                let mut samples = buffer.map_writable().unwrap();
 
-                let mut data = data.lock().unwrap();
-                if let Some(_source) = data.source_id.take() {
-                    println!("stop feeding");
-                    //source.remove();
+                let data = &mut *data;
+                data.generator.fill(&data.info, &mut samples);
+
+                data.num_samples += num_samples;
+            }
+
+            buffer.set_pts(pts);
+            buffer.set_duration(duration_buffer);
+        }
+
+        Some(buffer)
+    };
+
+    let mut feeder = match (video_info, file_source) {
+        // Frames are rendered one at a time, each timestamped from its number and the framerate
+        (Some(video_info), _) => {
+            let mut frames = FrameGenerator::new(&video_info);
+            let framerate = video_info.fps();
+            let max_bytes = MAX_BUFFERS * video_info.size() as u64;
+            Feeder::start(&appsrc, max_bytes, move || {
+                let pts = frame_seek::frame_to_time(frames.frames(), framerate)?;
+                if duration.map(|duration| pts >= duration).unwrap_or(false) {
+                    return None;
                 }
+                Some(frames.next_buffer())
             })
-            .build(),
-    );
+        }
+        // A file can be read from anywhere, so the appsrc can be seekable: on a seek,
+        // it asks us to continue from the new position through seek-data
+        (None, Some(source)) => {
+            println!("Duration: {}", source.duration());
+            appsrc
+                .set_property("duration", source.duration().nseconds())
+                .expect("Failed to set the duration");
+
+            let source = Arc::new(Mutex::new(source));
+            let source_seek = source.clone();
+            Feeder::start_seekable(
+                &appsrc,
+                max_bytes,
+                move || source.lock().unwrap().next_buffer(),
+                move |time| source_seek.lock().unwrap().seek(time),
+            )
+        }
+        (None, None) => Feeder::start(&appsrc, max_bytes, generate),
+    };
+
+    // "--seek <time>" seeks there after SEEK_DELAY, like chapter-4 does
+    let seek_target = match args.iter().position(|arg| arg == "--seek") {
+        Some(i) => match args
+            .get(i + 1)
+            .and_then(|time| clock_time::parse_time(time))
+        {
+            Some(target) => Some(target),
+            None => {
+                eprintln!("--seek needs a time");
+                return;
+            }
+        },
+        None => None,
+    };
 
     // configure appsink
-    appsink.set_caps(Some(&audio_caps));
-
+    appsink.set_caps(Some(&caps));
+
+    let analyze = args.iter().any(|arg| arg == "--analyze");
+    if let Some(i) = args.iter().position(|arg| arg == "--wav") {
+        match args.get(i + 1) {
+            Some(path) => data.lock().unwrap().wav_path = Some(path.clone()),
+            None => {
+                eprintln!("--wav needs a file name");
+                return;
+            }
+        }
+    }
+    let data_weak2 = Arc::downgrade(&data);
     let data_weak = Arc::downgrade(&data);
     appsink.set_callbacks(
         gst_app::AppSinkCallbacks::builder()
@@ -246,15 +448,42 @@ fn main() {
                     data.appsink.clone()
                 };
 
-                if let Ok(_sample) = appsink.pull_sample() {
+                if let Ok(sample) = appsink.pull_sample() {
+                    if let Err(err) = capture_sample(&mut data.lock().unwrap(), &sample) {
+                        eprintln!("Failed to write the WAV file: {}", err);
+                        return Err(gst::FlowError::Error);
+                    }
+
+                    // With "--analyze", we measure what we received
+                    if analyze {
+                        if let Some(analysis) = analyze_sample(&sample) {
+                            analysis.print(sample.buffer().and_then(|buffer| buffer.pts()));
+                        }
+                        return Ok(gst::FlowSuccess::Ok);
+                    }
+
                     use std::io::{self, Write};
-                    // The only thing we do in this example is print a * to indicate a received buffer
+                    // Otherwise the only thing we do in this example is print a * to indicate a received buffer
                     print!("*");
                     let _ = io::stdout().flush();
                 }
 
                 Ok(gst::FlowSuccess::Ok)
             })
+            .eos(move |_| {
+                // Everything was written, the header can get its sizes
+                let data = match data_weak2.upgrade() {
+                    Some(data) => data,
+                    None => return,
+                };
+                let mut data = data.lock().unwrap();
+                if let Some(mut wav) = data.wav.take() {
+                    match wav.finish() {
+                        Ok(()) => println!("\nWrote {} bytes of samples", wav.data_size()),
+                        Err(err) => eprintln!("Failed to finish the WAV file: {}", err),
+                    }
+                }
+            })
             .build(),
     );
 
@@ -279,14 +508,50 @@ fn main() {
         }
         _ => unreachable!(),
     });
+    let main_loop_clone = main_loop.clone();
+    bus.connect_message(Some("eos"), move |_, _| {
+        println!("\nEnd of stream");
+        main_loop_clone.quit();
+    });
     bus.add_signal_watch();
 
+    if let Some(interval) = queue_stats::report_interval(&args) {
+        let stats = QueueStats::new();
+        stats
+            .watch_all(pipeline.upcast_ref())
+            .expect("Failed to watch the queues");
+        glib::timeout_add_seconds(interval, move || {
+            stats.report();
+            glib::Continue(true)
+        });
+    }
+
+    if let Some(target) = seek_target {
+        let pipeline_weak = pipeline.downgrade();
+        glib::timeout_add_seconds(SEEK_DELAY, move || {
+            if let Some(pipeline) = pipeline_weak.upgrade() {
+                println!("\nSeeking to {}", target);
+                if pipeline
+                    .seek_simple(gst::SeekFlags::FLUSH | gst::SeekFlags::KEY_UNIT, target)
+                    .is_err()
+                {
+                    eprintln!("Seek failed!");
+                }
+            }
+            glib::Continue(false)
+        });
+    }
+
     pipeline
         .set_state(gst::State::Playing)
         .expect("Unable to set the pipeline to the `Playing` state.");
 
     main_loop.run();
 
+    // The feeder thread is done before the appsrc goes away
+    feeder.stop();
+    // Without an EOS (e.g. after an error) the WAV file is finished by dropping its writer
+    data.lock().unwrap().wav.take();
     pipeline
         .set_state(gst::State::Null)
         .expect("Unable to set the pipeline to the `Null` state.");
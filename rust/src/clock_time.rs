@@ -0,0 +1,65 @@
+#![allow(dead_code)]
+
+// Parses a time given by the user into a ClockTime.
+// Accepted forms: "30" or "1.5s" (seconds), "500ms", "40ns", "1:30" (m:s) and "0:01:30.5" (h:m:s).
+pub fn parse_time(text: &str) -> Option<gst::ClockTime> {
+    let text = text.trim();
+
+    let seconds = if text.contains(':') {
+        let mut seconds = 0.0;
+        for part in text.split(':') {
+            seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+        }
+        seconds
+    } else if let Some(ns) = text.strip_suffix("ns") {
+        return ns.parse::<u64>().ok().map(gst::ClockTime::from_nseconds);
+    } else if let Some(ms) = text.strip_suffix("ms") {
+        ms.parse::<f64>().ok()? / 1000.0
+    } else {
+        text.strip_suffix('s').unwrap_or(text).parse::<f64>().ok()?
+    };
+
+    if !seconds.is_finite() || seconds < 0.0 {
+        return None;
+    }
+
+    Some(gst::ClockTime::from_nseconds(
+        (seconds * gst::ClockTime::SECOND.nseconds() as f64).round() as u64,
+    ))
+}
+
+// The difference between two times, in signed nanoseconds
+pub fn diff_ns(a: gst::ClockTime, b: gst::ClockTime) -> i64 {
+    a.nseconds() as i64 - b.nseconds() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_form() {
+        let ms = gst::ClockTime::from_mseconds;
+        assert_eq!(parse_time("30"), Some(ms(30_000)));
+        assert_eq!(parse_time("1.5s"), Some(ms(1_500)));
+        assert_eq!(parse_time(" 500ms "), Some(ms(500)));
+        assert_eq!(parse_time("40ns"), Some(gst::ClockTime::from_nseconds(40)));
+        assert_eq!(parse_time("1:30"), Some(ms(90_000)));
+        assert_eq!(parse_time("0:01:30.5"), Some(ms(90_500)));
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        for text in ["", "s", "-1", "1.5ns", "1:x", "inf", "NaN", "10 s"] {
+            assert_eq!(parse_time(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn signed_difference() {
+        let a = gst::ClockTime::from_seconds(1);
+        let b = gst::ClockTime::from_mseconds(1_500);
+        assert_eq!(diff_ns(a, b), -500_000_000);
+        assert_eq!(diff_ns(b, a), 500_000_000);
+    }
+}
@@ -0,0 +1,670 @@
+use gst::prelude::*;
+use gst_app::{AppSink, AppSrc};
+use gst_audio::AudioInfo;
+use gst_video::VideoInfo;
+
+use std::env;
+use std::sync::{Arc, Mutex};
+
+#[path = "../audio_analysis.rs"]
+mod audio_analysis;
+#[path = "../audio_config.rs"]
+mod audio_config;
+#[path = "../clock_time.rs"]
+mod clock_time;
+#[path = "../feeder.rs"]
+mod feeder;
+#[path = "../frame_seek.rs"]
+mod frame_seek;
+#[path = "../video_frames.rs"]
+mod video_frames;
+#[path = "../waveform.rs"]
+mod waveform;
+
+use audio_config::AudioConfig;
+use feeder::Feeder;
+use video_frames::{FrameGenerator, VideoConfig};
+
+// Measures what a codec does to a known signal.
+// USAGE: codec-roundtrip <codec> [--duration <time>] [--options <encoder properties>] [--threshold <dB>]
+//        with the audio options of chapter-8 ("--wave", "--format", "--channels", "--rate",
+//        "--buffer-duration") for an audio codec, or its video ones ("--video-format", "--size",
+//        "--framerate") for a video codec
+// e.g.   codec-roundtrip opus --wave sine:freq=1000 --options bitrate=32000 --threshold 20
+//
+// The signal is generated like in chapter-8 (the audio options, "--wave", or the video options
+// of "--video" there), pushed through an appsrc into the encoder, straight into the matching
+// decoder, and converted back to the format of the input in an appsink.
+// Audio codecs delay the signal a bit, so the output is aligned with the input first, at the
+// delay where they correlate best. The noise is then what differs between the two, and the SNR
+// of each channel is the power of the input over the power of the noise.
+// Video frames are matched by their timestamps, the PSNR is computed over all the bytes of their
+// planes. With "--threshold", the exit status is 1 when the quality is below that. It is 1 as
+// well when the options are wrong or the pipeline can't be built or fails.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Audio,
+    Video,
+}
+
+struct Codec {
+    name: &'static str,
+    kind: Kind,
+    /// Parts of a pipeline description
+    encoder: &'static str,
+    decoder: &'static str,
+}
+
+const CODECS: [Codec; 6] = [
+    Codec {
+        name: "vorbis",
+        kind: Kind::Audio,
+        encoder: "vorbisenc",
+        decoder: "vorbisdec",
+    },
+    Codec {
+        name: "opus",
+        kind: Kind::Audio,
+        encoder: "opusenc",
+        decoder: "opusdec",
+    },
+    Codec {
+        name: "flac",
+        kind: Kind::Audio,
+        encoder: "flacenc",
+        decoder: "flacdec",
+    },
+    Codec {
+        name: "theora",
+        kind: Kind::Video,
+        encoder: "theoraenc",
+        decoder: "theoradec",
+    },
+    Codec {
+        name: "vp8",
+        kind: Kind::Video,
+        encoder: "vp8enc",
+        decoder: "vp8dec",
+    },
+    Codec {
+        name: "h264",
+        kind: Kind::Video,
+        encoder: "x264enc",
+        decoder: "h264parse ! avdec_h264",
+    },
+];
+
+const DEFAULT_DURATION: u64 = 5; // Seconds of signal
+const MAX_BUFFERS: u64 = 16; // Most buffers waiting in the appsrc
+
+// Most samples of delay between the input and the output, either way
+const MAX_LAG: usize = 2048;
+// Frames compared for each delay tried
+const ALIGN_WINDOW: usize = 8192;
+// Fewest frames compared for a delay to count, a small overlap correlates well by chance
+const MIN_OVERLAP: usize = ALIGN_WINDOW / 2;
+
+// What the appsink received
+#[derive(Debug, Default)]
+struct Received {
+    /// Audio samples, interleaved
+    bytes: Vec<u8>,
+    /// Video frames with their timestamps, in the layout of pack_planes()
+    frames: Vec<(Option<gst::ClockTime>, Vec<u8>)>,
+}
+
+// The visible bytes of every plane, without the padding at the end of the rows
+fn pack_planes(info: &VideoInfo, buffer: &gst::BufferRef) -> Option<Vec<u8>> {
+    let frame = gst_video::VideoFrameRef::from_buffer_ref_readable(buffer, info).ok()?;
+    let format_info = info.format_info();
+    let mut bytes = Vec::new();
+
+    for plane in 0..frame.n_planes() {
+        // The formats we generate have one component per plane or a single plane
+        let component = plane as u8;
+        let row = format_info.scale_width(component, info.width()) as usize
+            * format_info.pixel_stride()[plane as usize] as usize;
+        let rows = format_info.scale_height(component, info.height()) as usize;
+        let stride = frame.plane_stride()[plane as usize] as usize;
+        let data = frame.plane_data(plane).ok()?;
+        for y in 0..rows {
+            bytes.extend_from_slice(&data[y * stride..y * stride + row]);
+        }
+    }
+    Some(bytes)
+}
+
+// Samples of output delayed from input, negative when the output is early.
+// None when no delay leaves MIN_OVERLAP frames to compare.
+fn find_delay(input: &[f64], output: &[f64]) -> Option<isize> {
+    let mut best: Option<(isize, f64)> = None;
+
+    for lag in -(MAX_LAG as isize)..=MAX_LAG as isize {
+        let (mut correlation, mut energy, mut overlap) = (0.0, 0.0, 0);
+        for i in 0..ALIGN_WINDOW {
+            let j = i as isize + lag;
+            if j < 0 || i >= input.len() || j as usize >= output.len() {
+                continue;
+            }
+            let sample = output[j as usize];
+            correlation += input[i] * sample;
+            energy += sample * sample;
+            overlap += 1;
+        }
+        if overlap >= MIN_OVERLAP && energy > 0.0 {
+            // Normalized, or the louder parts of the output would win
+            let score = correlation / energy.sqrt();
+            let better = match best {
+                Some((_, best)) => score > best,
+                None => true,
+            };
+            if better {
+                best = Some((lag, score));
+            }
+        }
+    }
+    best.map(|(lag, _)| lag)
+}
+
+// Signal to noise ratio in dB, infinite without noise
+fn snr(signal: f64, noise: f64) -> f64 {
+    if noise > 0.0 {
+        10.0 * (signal / noise).log10()
+    } else {
+        f64::INFINITY
+    }
+}
+
+fn print_db(name: &str, value: f64) {
+    if value.is_infinite() {
+        println!("{}: lossless", name);
+    } else {
+        println!("{}: {:.2} dB", name, value);
+    }
+}
+
+// The SNR of the worst channel
+fn compare_audio(info: &AudioInfo, input: &[u8], output: &[u8]) -> Option<f64> {
+    let input = audio_analysis::deinterleave(info, input);
+    let output = audio_analysis::deinterleave(info, output);
+
+    let mix = |channels: &[Vec<f64>]| -> Vec<f64> {
+        let frames = channels.first().map(|samples| samples.len()).unwrap_or(0);
+        (0..frames)
+            .map(|i| channels.iter().map(|samples| samples[i]).sum::<f64>())
+            .collect()
+    };
+    let delay = match find_delay(&mix(&input), &mix(&output)) {
+        Some(delay) => delay,
+        None => {
+            eprintln!(
+                "Can't align the output with the input: it is silent or shorter than {} samples",
+                MIN_OVERLAP
+            );
+            return None;
+        }
+    };
+    println!(
+        "Delay: {} samples ({})",
+        delay,
+        audio_config::samples_to_time(delay.unsigned_abs() as u64, info.rate())
+    );
+
+    let mut worst: Option<f64> = None;
+    for (channel, (input, output)) in input.iter().zip(&output).enumerate() {
+        let pairs: Vec<(f64, f64)> = if delay >= 0 {
+            input
+                .iter()
+                .zip(output.iter().skip(delay as usize))
+                .map(|(a, b)| (*a, *b))
+                .collect()
+        } else {
+            input
+                .iter()
+                .skip(delay.unsigned_abs())
+                .zip(output)
+                .map(|(a, b)| (*a, *b))
+                .collect()
+        };
+        if pairs.is_empty() {
+            continue;
+        }
+
+        let signal: f64 = pairs.iter().map(|(a, _)| a * a).sum();
+        let noise: f64 = pairs.iter().map(|(a, b)| (a - b) * (a - b)).sum();
+        let value = snr(signal, noise);
+        print_db(
+            &format!("ch{}: SNR over {} samples", channel, pairs.len()),
+            value,
+        );
+        worst = Some(worst.map_or(value, |worst| worst.min(value)));
+    }
+    worst
+}
+
+// The PSNR over all the frames received
+fn compare_video(
+    info: &VideoInfo,
+    input: &[Vec<u8>],
+    output: &[(Option<gst::ClockTime>, Vec<u8>)],
+) -> Option<f64> {
+    let framerate = info.fps();
+    let (mut squares, mut count, mut compared, mut missing) = (0.0, 0u64, 0, 0);
+    let mut worst = f64::INFINITY;
+    for (pts, frame) in output {
+        // The nearest frame to the timestamp
+        let index = pts.map(|pts| frame_seek::time_to_frame(pts, framerate) as usize);
+        let original = match index.and_then(|index| input.get(index)) {
+            Some(original) if original.len() == frame.len() => original,
+            _ => {
+                missing += 1;
+                continue;
+            }
+        };
+
+        let frame_squares: f64 = original
+            .iter()
+            .zip(frame)
+            .map(|(a, b)| {
+                let difference = f64::from(*a) - f64::from(*b);
+                difference * difference
+            })
+            .sum();
+        worst = worst.min(snr(255.0 * 255.0, frame_squares / frame.len() as f64));
+        squares += frame_squares;
+        count += frame.len() as u64;
+        compared += 1;
+    }
+
+    println!("Compared {} frames, {} unmatched", compared, missing);
+    if compared == 0 {
+        return None;
+    }
+    print_db("Worst frame PSNR", worst);
+    let psnr = snr(255.0 * 255.0, squares / count as f64);
+    print_db("PSNR", psnr);
+    Some(psnr)
+}
+
+fn main() {
+    // Initialize GStreamer
+    if let Err(err) = gst::init() {
+        eprintln!("Failed to initialize Gst: {}", err);
+        std::process::exit(1);
+    }
+
+    let args: Vec<_> = env::args().collect();
+    let codec = match args
+        .get(1)
+        .and_then(|name| CODECS.iter().find(|c| c.name == name.as_str()))
+    {
+        Some(codec) => codec,
+        None => {
+            let names = |kind| {
+                CODECS
+                    .iter()
+                    .filter(|codec| codec.kind == kind)
+                    .map(|codec| codec.name)
+                    .collect::<Vec<_>>()
+                    .join("|")
+            };
+            eprintln!(
+                "USAGE: codec-roundtrip <{}|{}> [--duration <time>] [--options <encoder properties>] [--threshold <dB>]",
+                names(Kind::Audio),
+                names(Kind::Video)
+            );
+            eprintln!(
+                "  audio: [--wave <name>[:<parameter>=<value>,...]] [--format <format>] [--channels <channels>] [--rate <rate>] [--buffer-duration <time>]"
+            );
+            eprintln!(
+                "  video: [--video-format <RGBA|I420>] [--size <width>x<height>] [--framerate <frames>[/<seconds>]]"
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let value = |name: &str| -> Result<Option<&String>, String> {
+        match args.iter().position(|arg| arg == name) {
+            Some(i) => args
+                .get(i + 1)
+                .map(Some)
+                .ok_or_else(|| format!("{} needs a value", name)),
+            None => Ok(None),
+        }
+    };
+    let options = || -> Result<_, String> {
+        let duration = match value("--duration")? {
+            Some(time) => clock_time::parse_time(time).ok_or("--duration needs a time")?,
+            None => gst::ClockTime::from_seconds(DEFAULT_DURATION),
+        };
+        let encoder_options = value("--options")?.cloned().unwrap_or_default();
+        let threshold = match value("--threshold")? {
+            Some(threshold) => Some(
+                threshold
+                    .parse::<f64>()
+                    .map_err(|_| "--threshold needs a number of dB")?,
+            ),
+            None => None,
+        };
+        Ok((duration, encoder_options, threshold))
+    };
+    let (duration, encoder_options, threshold) = match options() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let elements = codec.encoder.split(" ! ").chain(codec.decoder.split(" ! "));
+    for name in elements {
+        if gst::ElementFactory::find(name).is_none() {
+            eprintln!("No such element factory: {}", name);
+            std::process::exit(1);
+        }
+    }
+
+    // The converters bring the signal to what the codec takes, and back to its format
+    let convert = match codec.kind {
+        Kind::Audio => "audioconvert ! audioresample",
+        Kind::Video => "videoconvert",
+    };
+    let description = format!(
+        "appsrc name=src ! {convert} ! {} {} ! {} ! {convert} ! appsink name=sink sync=false",
+        codec.encoder,
+        encoder_options,
+        codec.decoder,
+        convert = convert
+    );
+    println!("Pipeline: {}", description);
+    let pipeline = match gst::parse_launch(&description) {
+        Ok(pipeline) => pipeline.downcast::<gst::Pipeline>().unwrap(),
+        Err(err) => {
+            eprintln!("Failed to build the pipeline: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let appsrc = pipeline
+        .by_name("src")
+        .unwrap()
+        .dynamic_cast::<AppSrc>()
+        .expect("Source element is expected to be an appsrc!");
+    let appsink = pipeline
+        .by_name("sink")
+        .unwrap()
+        .dynamic_cast::<AppSink>()
+        .expect("Sink element is expected to be an appsink!");
+
+    // Everything we push is kept to compare with what comes back
+    let sent_bytes = Arc::new(Mutex::new(Vec::new()));
+    let sent_frames = Arc::new(Mutex::new(Vec::new()));
+    let received = Arc::new(Mutex::new(Received::default()));
+
+    let (caps, max_bytes, produce): (_, _, Box<dyn FnMut() -> Option<gst::Buffer> + Send>) =
+        match codec.kind {
+            Kind::Audio => {
+                let config = match AudioConfig::from_args(&args) {
+                    Ok(config) => config,
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        std::process::exit(1);
+                    }
+                };
+                let info = config.info().expect("Invalid audio format");
+                let mut generator = match waveform::from_args(&args, config.rate) {
+                    Ok(generator) => generator,
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        std::process::exit(1);
+                    }
+                };
+
+                let num_samples = config.samples_per_buffer();
+                let buffer_size = num_samples as usize * info.bpf() as usize;
+                let mut position = 0;
+                let sent = sent_bytes.clone();
+                let caps = info.to_caps().unwrap();
+                let produce = move || {
+                    let rate = info.rate();
+                    let pts = audio_config::samples_to_time(position, rate);
+                    if pts >= duration {
+                        return None;
+                    }
+                    let next = audio_config::samples_to_time(position + num_samples, rate);
+                    position += num_samples;
+
+                    let mut samples = vec![0; buffer_size];
+                    generator.fill(&info, &mut samples);
+                    sent.lock().unwrap().extend_from_slice(&samples);
+
+                    let mut buffer = gst::Buffer::from_mut_slice(samples);
+                    {
+                        let buffer = buffer.get_mut().unwrap();
+                        buffer.set_pts(pts);
+                        buffer.set_duration(next - pts);
+                    }
+                    Some(buffer)
+                };
+                (caps, MAX_BUFFERS * buffer_size as u64, Box::new(produce))
+            }
+            Kind::Video => {
+                let info = match VideoConfig::from_args(&args) {
+                    Ok(config) => config.info().expect("Invalid video format"),
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        std::process::exit(1);
+                    }
+                };
+
+                let mut frames = FrameGenerator::new(&info);
+                let framerate = info.fps();
+                let sent = sent_frames.clone();
+                let caps = info.to_caps().unwrap();
+                let max_bytes = MAX_BUFFERS * info.size() as u64;
+                let produce = move || {
+                    if frame_seek::frame_to_time(frames.frames(), framerate)? >= duration {
+                        return None;
+                    }
+                    let buffer = frames.next_buffer();
+                    sent.lock()
+                        .unwrap()
+                        .push(pack_planes(&info, &buffer).expect("Failed to read a frame"));
+                    Some(buffer)
+                };
+                (caps, max_bytes, Box::new(produce))
+            }
+        };
+
+    appsrc.set_caps(Some(&caps));
+    appsrc.set_format(gst::Format::Time);
+    // Converted back to the format of the input
+    appsink.set_caps(Some(&caps));
+
+    let kind = codec.kind;
+    let received_clone = received.clone();
+    appsink.set_callbacks(
+        gst_app::AppSinkCallbacks::builder()
+            .new_sample(move |appsink| {
+                let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                let buffer = sample.buffer().ok_or(gst::FlowError::Error)?;
+                let mut received = received_clone.lock().unwrap();
+                match kind {
+                    Kind::Audio => {
+                        let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+                        received.bytes.extend_from_slice(&map);
+                    }
+                    Kind::Video => {
+                        let info = sample
+                            .caps()
+                            .and_then(|caps| VideoInfo::from_caps(caps).ok())
+                            .ok_or(gst::FlowError::NotNegotiated)?;
+                        let frame = pack_planes(&info, buffer).ok_or(gst::FlowError::Error)?;
+                        received.frames.push((buffer.pts(), frame));
+                    }
+                }
+                Ok(gst::FlowSuccess::Ok)
+            })
+            .build(),
+    );
+
+    let mut feeder = Feeder::start(&appsrc, max_bytes, produce);
+
+    pipeline
+        .set_state(gst::State::Playing)
+        .expect("Unable to set the pipeline to the `Playing` state");
+
+    // The appsink has everything once the EOS made it through the pipeline
+    let bus = pipeline.bus().unwrap();
+    let mut failed = false;
+    for msg in bus.iter_timed(gst::ClockTime::NONE) {
+        use gst::MessageView;
+
+        match msg.view() {
+            MessageView::Eos(..) => break,
+            MessageView::Error(err) => {
+                eprintln!(
+                    "Error received from element {:?}: {}",
+                    err.src().map(|s| s.path_string()),
+                    err.error()
+                );
+                eprintln!("Debugging information: {:?}", err.debug());
+                failed = true;
+                break;
+            }
+            _ => (),
+        }
+    }
+
+    feeder.stop();
+    pipeline
+        .set_state(gst::State::Null)
+        .expect("Unable to set the pipeline to the `Null` state");
+    if failed {
+        std::process::exit(1);
+    }
+
+    let received = received.lock().unwrap();
+    let quality = match codec.kind {
+        Kind::Audio => {
+            let info = AudioInfo::from_caps(&caps).unwrap();
+            let sent = sent_bytes.lock().unwrap();
+            println!(
+                "Sent {} frames, received {}",
+                sent.len() / info.bpf() as usize,
+                received.bytes.len() / info.bpf() as usize
+            );
+            compare_audio(&info, &sent, &received.bytes)
+        }
+        Kind::Video => {
+            let info = VideoInfo::from_caps(&caps).unwrap();
+            let sent = sent_frames.lock().unwrap();
+            println!(
+                "Sent {} frames, received {}",
+                sent.len(),
+                received.frames.len()
+            );
+            compare_video(&info, &sent, &received.frames)
+        }
+    };
+
+    match (quality, threshold) {
+        (None, _) => {
+            eprintln!("Nothing to compare");
+            std::process::exit(1);
+        }
+        (Some(quality), Some(threshold)) if quality < threshold => {
+            eprintln!("Below the threshold of {} dB", threshold);
+            std::process::exit(1);
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Noise, which only correlates with itself at the right delay
+    fn noise(count: usize) -> Vec<f64> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..count)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_a_late_output() {
+        let input = noise(20_000);
+        let output = [vec![0.0; 312], input.clone()].concat();
+        assert_eq!(find_delay(&input, &output), Some(312));
+        assert_eq!(find_delay(&input, &input), Some(0));
+    }
+
+    #[test]
+    fn finds_an_early_output() {
+        let input = noise(20_000);
+        let output = input[100..].to_vec();
+        assert_eq!(find_delay(&input, &output), Some(-100));
+    }
+
+    #[test]
+    fn needs_enough_overlap() {
+        let input = noise(20_000);
+        // Matches at -1500, with only 1000 frames to compare
+        let output = input[1500..2500].to_vec();
+        assert_eq!(find_delay(&input, &output), None);
+        assert_eq!(find_delay(&input, &[]), None);
+    }
+
+    #[test]
+    fn identical_audio_is_lossless() {
+        gst::init().unwrap();
+        let info = AudioInfo::builder(gst_audio::AudioFormat::S16le, 48000, 2)
+            .build()
+            .unwrap();
+        let bytes: Vec<u8> = noise(2 * 10_000)
+            .iter()
+            .flat_map(|sample| ((sample * 10_000.0) as i16).to_le_bytes())
+            .collect();
+
+        assert_eq!(compare_audio(&info, &bytes, &bytes), Some(f64::INFINITY));
+        assert_eq!(compare_audio(&info, &bytes, &bytes[..400]), None);
+    }
+
+    #[test]
+    fn packs_planes_without_the_stride_padding() {
+        gst::init().unwrap();
+        // An odd width: the rows are padded, and the chroma planes are 3 samples wide
+        let info = VideoInfo::builder(gst_video::VideoFormat::I420, 5, 3)
+            .build()
+            .unwrap();
+        let sizes = [(5, 3), (3, 2), (3, 2)];
+
+        let buffer = gst::Buffer::with_size(info.size()).unwrap();
+        let mut frame = gst_video::VideoFrame::from_buffer_writable(buffer, &info).unwrap();
+        let mut expected = Vec::new();
+        for (plane, &(width, height)) in sizes.iter().enumerate() {
+            let stride = frame.plane_stride()[plane] as usize;
+            assert!(stride > width);
+            let data = frame.plane_data_mut(plane as u32).unwrap();
+            data.fill(0xff);
+            for y in 0..height {
+                for x in 0..width {
+                    let value = (plane * 100 + y * 10 + x) as u8;
+                    data[y * stride + x] = value;
+                    expected.push(value);
+                }
+            }
+        }
+        let buffer = frame.into_buffer();
+
+        assert_eq!(pack_planes(&info, &buffer).unwrap(), expected);
+    }
+}
@@ -0,0 +1,126 @@
+#![allow(dead_code)]
+
+use glib::translate::{from_glib, IntoGlib};
+use gst::prelude::*;
+
+// decodebin (and therefore uridecodebin and playbin) builds its list of candidate decoders from the
+// registry: only factories with a rank of at least MARGINAL are considered, and for every stream the
+// candidates are tried from the highest rank to the lowest (this is what autoplug-factories and
+// autoplug-sort do by default, before autoplug-select gets a chance to see them).
+// So instead of answering autoplug-select ourselves we change the ranks before the pipeline is built:
+// blocked decoders are dropped to NONE and preferred decoders are pushed above PRIMARY.
+
+// A pattern matches a decoder factory either by its exact name ("avdec_h264")
+// or by one of the components of its klass ("Hardware" in "Codec/Decoder/Video/Hardware").
+
+#[derive(Debug, Default, Clone)]
+pub struct DecoderPolicy {
+    /// Decoders to try first, the most preferred one first
+    pub prefer: Vec<String>,
+    /// Decoders that must never be autoplugged
+    pub block: Vec<String>,
+}
+
+impl DecoderPolicy {
+    // Reads every "--prefer-decoder <pattern>" and "--block-decoder <pattern>" pair from the arguments
+    pub fn from_args(args: &[String]) -> DecoderPolicy {
+        let mut policy = DecoderPolicy::default();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--prefer-decoder" => policy.prefer.extend(args.next().cloned()),
+                "--block-decoder" => policy.block.extend(args.next().cloned()),
+                _ => (),
+            }
+        }
+
+        policy
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefer.is_empty() && self.block.is_empty()
+    }
+
+    // Changes the ranks of the matching decoder factories in the registry.
+    // This has to be done before the decodebin starts autoplugging (so before going to PAUSED).
+    pub fn apply(&self) {
+        if self.is_empty() {
+            return;
+        }
+
+        let decoders = gst::ElementFactory::list_get_elements(
+            gst::ElementFactoryType::DECODER,
+            gst::Rank::None,
+        );
+
+        for factory in decoders.iter() {
+            if self.block.iter().any(|pattern| matches(pattern, factory)) {
+                println!("Blocking decoder {}", factory.name());
+                factory.set_rank(gst::Rank::None);
+            } else if let Some(index) = self
+                .prefer
+                .iter()
+                .position(|pattern| matches(pattern, factory))
+            {
+                // The first preferred pattern ends up with the highest rank
+                let rank = gst::Rank::Primary.into_glib() + (self.prefer.len() - index) as i32;
+                println!("Preferring decoder {} (rank {})", factory.name(), rank);
+                factory.set_rank(unsafe { from_glib(rank) });
+            }
+        }
+    }
+}
+
+fn matches(pattern: &str, factory: &gst::ElementFactory) -> bool {
+    if factory.name().as_str() == pattern {
+        return true;
+    }
+
+    let klass = factory.metadata("klass").unwrap_or("");
+    klass
+        .split('/')
+        .any(|component| component.eq_ignore_ascii_case(pattern))
+}
+
+// Logs which decoder got plugged for each stream of a decodebin based bin (uridecodebin, playbin...).
+// decodebin creates its decoders inside nested bins, so we need deep-element-added rather than element-added.
+pub fn log_chosen_decoders(bin: &gst::Bin) {
+    bin.connect_deep_element_added(|_, _, element| {
+        let factory = match element.factory() {
+            Some(factory) => factory,
+            None => return,
+        };
+
+        // Only codecs: decodebin and friends are "Generic/Bin/Decoder"
+        let klass = factory.metadata("klass").unwrap_or("");
+        let tokens: Vec<&str> = klass.split('/').collect();
+        if !tokens.contains(&"Codec") || !tokens.contains(&"Decoder") || element.is::<gst::Bin>() {
+            return;
+        }
+
+        let factory_name = factory.name();
+        println!("Decoder chosen: {} ({})", factory_name, klass);
+
+        // The decoder doesn't know which stream it handles yet, so wait for the caps on its sink pad
+        let sink_pad = match element.static_pad("sink") {
+            Some(pad) => pad,
+            None => return,
+        };
+        let element_name = element.name();
+        sink_pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+            if let Some(gst::PadProbeData::Event(ref event)) = info.data {
+                if let gst::EventView::Caps(caps) = event.view() {
+                    println!(
+                        "Decoder {} ({}) handles stream {}",
+                        element_name,
+                        factory_name,
+                        caps.caps()
+                    );
+                    return gst::PadProbeReturn::Remove;
+                }
+            }
+            gst::PadProbeReturn::Ok
+        });
+    });
+}
@@ -0,0 +1,256 @@
+use glib::translate::IntoGlib;
+use gst::prelude::*;
+use serde_json::{json, Value};
+
+use std::env;
+
+#[path = "../element_info.rs"]
+mod element_info;
+
+use element_info::{PropertyInfo, SignalInfo};
+
+// A small gst-inspect: prints everything the registry and the element class know about factories.
+// USAGE: element-inspector [--json] <factory> [<factory>...]
+
+fn print_factory(factory: &gst::ElementFactory) {
+    println!("===== {} =====", factory.name());
+
+    println!("Factory Details:");
+    println!(
+        "  {:<20} {:?} ({})",
+        "rank",
+        factory.rank(),
+        factory.rank().into_glib()
+    );
+    for key in factory.metadata_keys() {
+        println!(
+            "  {:<20} {}",
+            key,
+            factory.metadata(&key).unwrap_or_default()
+        );
+    }
+
+    println!("Plugin Details:");
+    match factory.plugin() {
+        Some(plugin) => {
+            println!("  {:<20} {}", "name", plugin.plugin_name());
+            println!("  {:<20} {}", "description", plugin.description());
+            println!(
+                "  {:<20} {}",
+                "filename",
+                plugin
+                    .filename()
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_default()
+            );
+            println!("  {:<20} {}", "version", plugin.version());
+            println!("  {:<20} {}", "license", plugin.license());
+            println!("  {:<20} {}", "source module", plugin.source());
+            println!("  {:<20} {}", "binary package", plugin.package());
+            println!("  {:<20} {}", "origin", plugin.origin());
+        }
+        None => println!("  (static element)"),
+    }
+
+    println!("Pad Templates:");
+    if factory.num_pad_templates() == 0u32 {
+        println!("  None");
+    } else {
+        element_info::print_pad_templates(factory, "  ");
+    }
+
+    let element = match factory.create(None) {
+        Ok(element) => element,
+        Err(err) => {
+            eprintln!("Failed to create a {}: {}", factory.name(), err);
+            return;
+        }
+    };
+
+    println!("Element Properties:");
+    for property in element_info::properties(&element) {
+        print_property(&property);
+    }
+
+    let signals = element_info::signals(element.type_());
+    println!("Element Signals:");
+    for signal in signals.iter().filter(|signal| !signal.action) {
+        print_signal(signal);
+    }
+    println!("Element Actions:");
+    for signal in signals.iter().filter(|signal| signal.action) {
+        print_signal(signal);
+    }
+}
+
+fn print_property(property: &PropertyInfo) {
+    println!("  {:<20}: {}", property.name, property.blurb);
+    println!("  {:<20}  flags: {}", "", property.flags);
+
+    let mut line = format!("  {:<20}  Type: {}", "", property.type_name);
+    if let Some((min, max)) = &property.range {
+        line.push_str(&format!(" Range: {} - {}", min, max));
+    }
+    if let Some(default) = &property.default {
+        line.push_str(&format!(" Default: {}", default));
+    }
+    println!("{}", line);
+
+    for (value, nick) in &property.values {
+        println!("  {:<20}    ({}): {}", "", value, nick);
+    }
+}
+
+fn print_signal(signal: &SignalInfo) {
+    println!(
+        "  {:<20}: {} user_function ({}) [{}]",
+        format!("\"{}\"", signal.name),
+        signal.return_type,
+        signal.param_types.join(", "),
+        signal.owner
+    );
+}
+
+fn caps_json(caps: &gst::CapsRef) -> Value {
+    if caps.is_any() {
+        return json!("ANY");
+    }
+
+    Value::Array(
+        caps.iter()
+            .map(|structure| {
+                let fields: serde_json::Map<String, Value> = structure
+                    .iter()
+                    .map(|(field, value)| {
+                        // Like the text output, values that can't be serialized are shown as Debug
+                        let value = value
+                            .serialize()
+                            .map(|value| value.to_string())
+                            .unwrap_or_else(|_| format!("{:?}", value));
+                        (field.to_string(), json!(value))
+                    })
+                    .collect();
+                json!({ "name": structure.name(), "fields": fields })
+            })
+            .collect(),
+    )
+}
+
+fn factory_json(factory: &gst::ElementFactory) -> Value {
+    let metadata: serde_json::Map<String, Value> = factory
+        .metadata_keys()
+        .iter()
+        .map(|key| {
+            (
+                key.to_string(),
+                json!(factory.metadata(key).unwrap_or_default()),
+            )
+        })
+        .collect();
+
+    let plugin = factory.plugin().map(|plugin| {
+        json!({
+            "name": plugin.plugin_name().as_str(),
+            "description": plugin.description().as_str(),
+            "filename": plugin.filename().map(|path| path.display().to_string()),
+            "version": plugin.version().as_str(),
+            "license": plugin.license().as_str(),
+            "source": plugin.source().as_str(),
+            "package": plugin.package().as_str(),
+            "origin": plugin.origin().as_str(),
+        })
+    });
+
+    let pad_templates: Vec<Value> = factory
+        .static_pad_templates()
+        .iter()
+        .map(|template| {
+            json!({
+                "name": template.name_template().as_str(),
+                "direction": element_info::direction_name(template.direction()),
+                "presence": element_info::presence_name(template.presence()),
+                "caps": caps_json(&template.caps()),
+            })
+        })
+        .collect();
+
+    let mut json = json!({
+        "name": factory.name().as_str(),
+        "rank": factory.rank().into_glib(),
+        "metadata": metadata,
+        "plugin": plugin,
+        "pad_templates": pad_templates,
+    });
+
+    if let Ok(element) = factory.create(None) {
+        let properties: Vec<Value> = element_info::properties(&element)
+            .into_iter()
+            .map(|property| {
+                json!({
+                    "name": property.name,
+                    "blurb": property.blurb,
+                    "type": property.type_name,
+                    "flags": property.flags,
+                    "default": property.default,
+                    "range": property.range.map(|(min, max)| json!({ "min": min, "max": max })),
+                    "values": property
+                        .values
+                        .into_iter()
+                        .map(|(value, nick)| json!({ "value": value, "nick": nick }))
+                        .collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+
+        let (actions, signals): (Vec<_>, Vec<_>) = element_info::signals(element.type_())
+            .into_iter()
+            .partition(|signal| signal.action);
+        let signal_json = |signal: SignalInfo| {
+            json!({
+                "name": signal.name,
+                "owner": signal.owner,
+                "return_type": signal.return_type,
+                "param_types": signal.param_types,
+            })
+        };
+
+        json["properties"] = json!(properties);
+        json["signals"] = json!(signals.into_iter().map(signal_json).collect::<Vec<_>>());
+        json["actions"] = json!(actions.into_iter().map(signal_json).collect::<Vec<_>>());
+    }
+
+    json
+}
+
+fn main() {
+    if let Err(err) = gst::init() {
+        eprintln!("Failed to initialize Gst: {}", err);
+        return;
+    }
+
+    let args: Vec<_> = env::args().skip(1).collect();
+    let as_json = args.iter().any(|arg| arg == "--json");
+    let names: Vec<_> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+
+    if names.is_empty() {
+        eprintln!("USAGE: element-inspector [--json] <factory> [<factory>...]");
+        return;
+    }
+
+    let mut factories = Vec::new();
+    for name in names {
+        match gst::ElementFactory::find(name) {
+            Some(factory) => factories.push(factory),
+            None => eprintln!("No such element factory: {}", name),
+        }
+    }
+
+    if as_json {
+        let json: Vec<Value> = factories.iter().map(factory_json).collect();
+        println!("{}", serde_json::to_string_pretty(&json).unwrap());
+    } else {
+        for factory in &factories {
+            print_factory(factory);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+#![allow(dead_code)]
+
+use glib::translate::{from_glib, IntoGlib};
+use gst::prelude::*;
+
+use std::ffi::CStr;
+
+pub fn print_caps(caps: &gst::CapsRef, prefix: &str) {
+    if caps.is_any() {
+        println!("{}ANY", prefix);
+        return;
+    }
+
+    if caps.is_empty() {
+        println!("{}EMPTY", prefix);
+        return;
+    }
+
+    for structure in caps.iter() {
+        println!("{}{}", prefix, structure.name());
+        for (field, value) in structure.iter() {
+            // Not every type of value can be serialized
+            match value.serialize() {
+                Ok(serialized) => println!("{}  {}:{}", prefix, field, serialized),
+                Err(_) => println!("{}  {}:{:?}", prefix, field, value),
+            }
+        }
+    }
+}
+
+pub fn direction_name(direction: gst::PadDirection) -> &'static str {
+    match direction {
+        gst::PadDirection::Src => "SRC",
+        gst::PadDirection::Sink => "SINK",
+        _ => "UNKNOWN!!!",
+    }
+}
+
+pub fn presence_name(presence: gst::PadPresence) -> &'static str {
+    match presence {
+        gst::PadPresence::Always => "Always",
+        gst::PadPresence::Sometimes => "Sometimes",
+        gst::PadPresence::Request => "On request",
+        _ => "UNKNOWN!!!",
+    }
+}
+
+// The pad templates of a factory, with their direction, availability and caps (see chapter-6)
+
+pub fn print_pad_templates(factory: &gst::ElementFactory, prefix: &str) {
+    for pad_template in factory.static_pad_templates() {
+        println!(
+            "{}{} template: '{}'",
+            prefix,
+            direction_name(pad_template.direction()),
+            pad_template.name_template()
+        );
+        println!(
+            "{}Availability: {}",
+            prefix,
+            presence_name(pad_template.presence())
+        );
+
+        let caps = pad_template.caps();
+        println!("{}Capabilities:", prefix);
+        print_caps(&caps, &format!("{}  ", prefix));
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PropertyInfo {
+    pub name: String,
+    pub blurb: String,
+    pub type_name: String,
+    pub flags: String,
+    pub default: Option<String>,
+    /// Minimum and maximum of numeric properties
+    pub range: Option<(String, String)>,
+    /// Possible values of enum and flags properties, with their nick
+    pub values: Vec<(i64, String)>,
+}
+
+macro_rules! range_of {
+    ($pspec:expr, $($spec_type:ty),*) => {
+        None$(.or_else(|| {
+            $pspec
+                .downcast_ref::<$spec_type>()
+                .map(|spec| (spec.minimum().to_string(), spec.maximum().to_string()))
+        }))*
+    };
+}
+
+pub fn properties(element: &gst::Element) -> Vec<PropertyInfo> {
+    element
+        .list_properties()
+        .iter()
+        .map(|pspec| {
+            let value_type = pspec.value_type();
+
+            let mut values = Vec::new();
+            if let Some(enum_class) = glib::EnumClass::new(value_type) {
+                for value in enum_class.values() {
+                    values.push((i64::from(value.value()), value.nick().to_string()));
+                }
+            } else if let Some(flags_class) = glib::FlagsClass::new(value_type) {
+                for value in flags_class.values() {
+                    values.push((i64::from(value.value()), value.nick().to_string()));
+                }
+            }
+
+            PropertyInfo {
+                name: pspec.name().to_string(),
+                blurb: pspec.blurb().to_string(),
+                type_name: value_type.name().to_string(),
+                flags: format!("{:?}", pspec.flags()),
+                default: pspec
+                    .default_value()
+                    .serialize()
+                    .ok()
+                    .map(|value| value.to_string()),
+                range: range_of!(
+                    pspec,
+                    glib::ParamSpecInt,
+                    glib::ParamSpecUInt,
+                    glib::ParamSpecInt64,
+                    glib::ParamSpecUInt64,
+                    glib::ParamSpecLong,
+                    glib::ParamSpecULong,
+                    glib::ParamSpecFloat,
+                    glib::ParamSpecDouble
+                ),
+                values,
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct SignalInfo {
+    pub name: String,
+    /// The type that defines the signal (the element itself or one of its parents)
+    pub owner: String,
+    pub return_type: String,
+    pub param_types: Vec<String>,
+    /// Action signals are meant to be emitted by the application, not connected to
+    pub action: bool,
+}
+
+// The bindings can't enumerate the signals of a type, so we ask GObject directly,
+// for the type and each of its parents up to (but not including) GObject.
+pub fn signals(type_: glib::Type) -> Vec<SignalInfo> {
+    let mut signals = Vec::new();
+    let mut current = Some(type_);
+
+    while let Some(ty) = current.filter(|ty| *ty != glib::Type::OBJECT) {
+        unsafe {
+            let mut n_ids = 0;
+            let ids = glib::gobject_ffi::g_signal_list_ids(ty.into_glib(), &mut n_ids);
+
+            for i in 0..n_ids as usize {
+                let mut query = std::mem::MaybeUninit::<glib::gobject_ffi::GSignalQuery>::zeroed();
+                glib::gobject_ffi::g_signal_query(*ids.add(i), query.as_mut_ptr());
+                let query = query.assume_init();
+                if query.signal_id == 0 {
+                    continue;
+                }
+
+                // The lowest bit of the types is the G_SIGNAL_TYPE_STATIC_SCOPE marker
+                let type_name = |gtype: glib::ffi::GType| {
+                    let ty: glib::Type = from_glib(gtype & !1);
+                    ty.name().to_string()
+                };
+
+                signals.push(SignalInfo {
+                    name: CStr::from_ptr(query.signal_name)
+                        .to_string_lossy()
+                        .into_owned(),
+                    owner: ty.name().to_string(),
+                    return_type: type_name(query.return_type),
+                    param_types: (0..query.n_params as usize)
+                        .map(|param| type_name(*query.param_types.add(param)))
+                        .collect(),
+                    action: query.signal_flags & glib::gobject_ffi::G_SIGNAL_ACTION != 0,
+                });
+            }
+
+            glib::ffi::g_free(ids as glib::ffi::gpointer);
+        }
+
+        current = ty.parent();
+    }
+
+    signals
+}
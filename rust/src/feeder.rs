@@ -0,0 +1,350 @@
+#![allow(dead_code)]
+
+use gst_app::AppSrc;
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+// Feeds an appsrc from a thread of its own, following its backpressure.
+// appsrc emits need-data when its internal queue runs low and enough-data once it holds
+// max-bytes. The thread produces buffers while data is needed and sleeps on a condition variable
+// otherwise, so at most max-bytes (plus one buffer) are waiting in the appsrc.
+// It stops when the producer has nothing more (the appsrc then gets an EOS) or when stop() is
+// called, which dropping the Feeder does too.
+//
+// A seekable feeder also handles seek-data, which appsrc emits for a seek in the pipeline: the
+// producer is repositioned and the buffers made before the seek are never pushed. At the end
+// of the stream it waits for a seek back instead of stopping.
+
+#[derive(Debug, Default)]
+struct State {
+    /// Between need-data and enough-data
+    wanted: bool,
+    stopped: bool,
+    /// Number of need-data so far
+    needs: u64,
+    /// Number of seeks so far
+    seeks: u64,
+}
+
+pub struct Feeder {
+    state: Arc<(Mutex<State>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Feeder {
+    // `produce` is called for every buffer on the feeder thread, None means end of stream.
+    // This takes over the callbacks of the appsrc.
+    pub fn start<F>(appsrc: &AppSrc, max_bytes: u64, produce: F) -> Feeder
+    where
+        F: FnMut() -> Option<gst::Buffer> + Send + 'static,
+    {
+        Feeder::spawn(appsrc, max_bytes, produce, None)
+    }
+
+    // Same for an appsrc of stream-type seekable: `seek` repositions the producer to the time
+    // (in nanoseconds with the Time format) and returns whether it could
+    pub fn start_seekable<F, S>(appsrc: &AppSrc, max_bytes: u64, produce: F, seek: S) -> Feeder
+    where
+        F: FnMut() -> Option<gst::Buffer> + Send + 'static,
+        S: FnMut(u64) -> bool + Send + 'static,
+    {
+        appsrc.set_stream_type(gst_app::AppStreamType::Seekable);
+        Feeder::spawn(appsrc, max_bytes, produce, Some(Box::new(seek)))
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn spawn<F>(
+        appsrc: &AppSrc,
+        max_bytes: u64,
+        mut produce: F,
+        seek: Option<Box<dyn FnMut(u64) -> bool + Send>>,
+    ) -> Feeder
+    where
+        F: FnMut() -> Option<gst::Buffer> + Send + 'static,
+    {
+        let state = Arc::new((Mutex::new(State::default()), Condvar::new()));
+        // Held while pushing and while seeking, so no buffer made before a seek is pushed after it.
+        // Not the state lock: pushing can emit enough-data right away, which takes that one.
+        let push_lock = Arc::new(Mutex::new(()));
+        let seekable = seek.is_some();
+
+        appsrc.set_max_bytes(max_bytes);
+        // push_buffer() never blocks, enough-data tells us to stop instead
+        appsrc.set_block(false);
+
+        let state_need = state.clone();
+        let state_enough = state.clone();
+        let mut callbacks = gst_app::AppSrcCallbacks::builder()
+            .need_data(move |_, _size| {
+                let (lock, cvar) = &*state_need;
+                let mut state = lock.lock().unwrap();
+                state.wanted = true;
+                state.needs += 1;
+                cvar.notify_one();
+            })
+            .enough_data(move |_| {
+                let (lock, _) = &*state_enough;
+                lock.lock().unwrap().wanted = false;
+            });
+        if let Some(seek) = seek {
+            let state_seek = state.clone();
+            let push_lock_seek = push_lock.clone();
+            let seek = Mutex::new(seek);
+            callbacks = callbacks.seek_data(move |_, offset| {
+                let _pushing = push_lock_seek.lock().unwrap();
+                state_seek.0.lock().unwrap().seeks += 1;
+                (seek.lock().unwrap())(offset)
+            });
+        }
+        appsrc.set_callbacks(callbacks.build());
+
+        let appsrc = appsrc.clone();
+        let thread_state = state.clone();
+        let thread = thread::spawn(move || {
+            let (lock, cvar) = &*thread_state;
+            loop {
+                let seeks = {
+                    let mut state = lock.lock().unwrap();
+                    while !state.wanted && !state.stopped {
+                        state = cvar.wait(state).unwrap();
+                    }
+                    if state.stopped {
+                        break;
+                    }
+                    state.seeks
+                };
+
+                let buffer = produce();
+
+                // push_buffer() doesn't block, so seeks never wait long for this
+                let _pushing = push_lock.lock().unwrap();
+                let needs = {
+                    let state = lock.lock().unwrap();
+                    if state.seeks != seeks {
+                        // Made before a seek, the next one will come from the new position
+                        continue;
+                    }
+                    state.needs
+                };
+
+                let buffer = match buffer {
+                    Some(buffer) => buffer,
+                    None => {
+                        let _ = appsrc.end_of_stream();
+                        if !seekable {
+                            break;
+                        }
+                        // Nothing more until a seek asks for data again
+                        let mut state = lock.lock().unwrap();
+                        if state.needs == needs {
+                            state.wanted = false;
+                        }
+                        continue;
+                    }
+                };
+
+                match appsrc.push_buffer(buffer) {
+                    Ok(_) => (),
+                    // Flushing (a flushing seek, or the pipeline shutting down): wait until
+                    // data is needed again or we are stopped. A need-data after the flush may
+                    // already be there, that one still counts.
+                    Err(gst::FlowError::Flushing) => {
+                        let mut state = lock.lock().unwrap();
+                        if state.needs == needs {
+                            state.wanted = false;
+                        }
+                    }
+                    Err(err) => {
+                        eprintln!("Failed to push a buffer: {:?}", err);
+                        break;
+                    }
+                }
+            }
+        });
+
+        Feeder {
+            state,
+            thread: Some(thread),
+        }
+    }
+
+    // Stops the thread and waits for it
+    pub fn stop(&mut self) {
+        {
+            let (lock, cvar) = &*self.state;
+            lock.lock().unwrap().stopped = true;
+            cvar.notify_one();
+        }
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Feeder {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gst::prelude::*;
+    use gst_app::AppSink;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    // appsrc ! appsink, with room for a single sample in the appsink
+    fn pipeline() -> (gst::Pipeline, AppSrc, AppSink) {
+        gst::init().unwrap();
+        let pipeline = gst::parse_launch(
+            "appsrc name=src format=time caps=application/x-test ! \
+             appsink name=sink sync=false max-buffers=1",
+        )
+        .unwrap()
+        .downcast::<gst::Pipeline>()
+        .unwrap();
+        let appsrc = pipeline
+            .by_name("src")
+            .unwrap()
+            .downcast::<AppSrc>()
+            .unwrap();
+        let appsink = pipeline
+            .by_name("sink")
+            .unwrap()
+            .downcast::<AppSink>()
+            .unwrap();
+        (pipeline, appsrc, appsink)
+    }
+
+    // 16 bytes holding the index, lasting 10 ms each
+    fn buffer(index: u64) -> gst::Buffer {
+        let mut buffer = gst::Buffer::from_slice(vec![index as u8; 16]);
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(10 * index * gst::ClockTime::MSECOND);
+            buffer.set_duration(10 * gst::ClockTime::MSECOND);
+        }
+        buffer
+    }
+
+    fn pull(appsink: &AppSink) -> gst::Sample {
+        appsink
+            .try_pull_sample(gst::ClockTime::SECOND)
+            .expect("no sample within a second")
+    }
+
+    fn first_byte(sample: &gst::Sample) -> u8 {
+        sample.buffer().unwrap().map_readable().unwrap()[0]
+    }
+
+    #[test]
+    fn feeds_until_the_producer_ends() {
+        let (pipeline, appsrc, appsink) = pipeline();
+        let mut index = 0;
+        let _feeder = Feeder::start(&appsrc, 64, move || {
+            index += 1;
+            if index <= 5 {
+                Some(buffer(index))
+            } else {
+                None
+            }
+        });
+        pipeline.set_state(gst::State::Playing).unwrap();
+
+        for index in 1..=5 {
+            assert_eq!(first_byte(&pull(&appsink)), index);
+        }
+        assert!(appsink.pull_sample().is_err());
+        assert!(appsink.is_eos());
+
+        pipeline.set_state(gst::State::Null).unwrap();
+    }
+
+    #[test]
+    fn pauses_on_enough_data_and_resumes_on_need_data() {
+        let (pipeline, appsrc, appsink) = pipeline();
+        let produced = Arc::new(AtomicU64::new(0));
+        let produced_clone = produced.clone();
+        let feeder = Feeder::start(&appsrc, 64, move || {
+            Some(buffer(produced_clone.fetch_add(1, Ordering::SeqCst)))
+        });
+        pipeline.set_state(gst::State::Playing).unwrap();
+
+        // Nobody pulls, so the appsink blocks the pipeline and the appsrc fills up
+        thread::sleep(Duration::from_millis(200));
+        let paused_at = produced.load(Ordering::SeqCst);
+        assert!(!feeder.state.0.lock().unwrap().wanted);
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(produced.load(Ordering::SeqCst), paused_at);
+        // 64 bytes in the appsrc, one buffer past them and a few held downstream
+        assert!(paused_at < 10, "{} buffers made", paused_at);
+
+        // Pulling more than was queued needs the feeder to resume
+        for index in 0..paused_at + 5 {
+            assert_eq!(first_byte(&pull(&appsink)), index as u8);
+        }
+        assert!(produced.load(Ordering::SeqCst) > paused_at);
+        assert!(feeder.state.0.lock().unwrap().needs > 1);
+
+        pipeline.set_state(gst::State::Null).unwrap();
+    }
+
+    #[test]
+    fn buffers_made_before_a_seek_are_dropped() {
+        let (pipeline, appsrc, appsink) = pipeline();
+        let position = Arc::new(AtomicU64::new(0));
+        let position_seek = position.clone();
+        // The producer stops in the middle of its third buffer until the seek is done
+        let (holding_sender, holding) = mpsc::channel();
+        let (release, release_receiver) = mpsc::channel::<()>();
+
+        let _feeder = Feeder::start_seekable(
+            &appsrc,
+            64,
+            move || {
+                let index = position.fetch_add(1, Ordering::SeqCst);
+                let buffer = buffer(index);
+                if index == 2 {
+                    holding_sender.send(()).unwrap();
+                    let _ = release_receiver.recv_timeout(Duration::from_secs(1));
+                }
+                Some(buffer)
+            },
+            move |offset| {
+                position_seek.store(
+                    offset / (10 * gst::ClockTime::MSECOND).nseconds(),
+                    Ordering::SeqCst,
+                );
+                true
+            },
+        );
+        pipeline.set_state(gst::State::Paused).unwrap();
+
+        holding.recv_timeout(Duration::from_secs(1)).unwrap();
+        // The first buffer was pushed before, so the appsink prerolls meanwhile
+        let _ = pipeline.state(gst::ClockTime::SECOND);
+        pipeline
+            .seek_simple(
+                gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE,
+                gst::ClockTime::SECOND,
+            )
+            .unwrap();
+        release.send(()).unwrap();
+
+        // Without the check the buffer at 20 ms, made before the seek, would come first
+        let preroll = appsink
+            .try_pull_preroll(gst::ClockTime::SECOND)
+            .expect("no preroll after the seek");
+        assert_eq!(
+            preroll.buffer().unwrap().pts(),
+            Some(gst::ClockTime::SECOND)
+        );
+
+        pipeline.set_state(gst::State::Null).unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::audio_config::{self, AudioConfig};
+use crate::wav;
+
+// Audio samples read from a file, one buffer at a time, for a seekable appsrc.
+// A ".wav" file tells its own format, any other file is taken as raw PCM in the format of the
+// AudioConfig ("--format", "--channels", "--rate").
+// Seeking only moves the read position: the next buffer starts at the frame of the new time,
+// and its timestamp is that time, as the appsrc expects after a seek.
+
+#[derive(Debug)]
+pub struct FileSource {
+    reader: BufReader<File>,
+    info: gst_audio::AudioInfo,
+    /// Where the samples start in the file
+    data_offset: u64,
+    /// The file channel of each channel, for WAV files that don't have GStreamer's order
+    channel_order: Option<Vec<usize>>,
+    /// Frames in the file
+    frames: u64,
+    /// Next frame to read
+    position: u64,
+    frames_per_buffer: u64,
+}
+
+impl FileSource {
+    pub fn open<P: AsRef<Path>>(path: P, config: &AudioConfig) -> io::Result<FileSource> {
+        let path = path.as_ref();
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let is_wav = path
+            .extension()
+            .map(|extension| extension.eq_ignore_ascii_case("wav"))
+            .unwrap_or(false);
+        let (info, data_offset, data_size, channel_order) = if is_wav {
+            let header = wav::read_header(&mut reader)?;
+            (
+                header.info,
+                header.data_offset,
+                header.data_size,
+                header.channel_order,
+            )
+        } else {
+            let info = config
+                .info()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid format"))?;
+            let size = reader.seek(SeekFrom::End(0))?;
+            reader.seek(SeekFrom::Start(0))?;
+            (info, 0, size, None)
+        };
+
+        // The same buffer duration as generated audio
+        let frames_per_buffer = AudioConfig {
+            rate: info.rate(),
+            ..config.clone()
+        }
+        .samples_per_buffer();
+
+        Ok(FileSource {
+            reader,
+            frames: data_size / u64::from(info.bpf()),
+            info,
+            data_offset,
+            channel_order,
+            position: 0,
+            frames_per_buffer,
+        })
+    }
+
+    pub fn info(&self) -> &gst_audio::AudioInfo {
+        &self.info
+    }
+
+    pub fn duration(&self) -> gst::ClockTime {
+        audio_config::samples_to_time(self.frames, self.info.rate())
+    }
+
+    // The next buffer of samples, None at the end of the file
+    pub fn next_buffer(&mut self) -> Option<gst::Buffer> {
+        let frames = self.frames_per_buffer.min(self.frames - self.position);
+        if frames == 0 {
+            return None;
+        }
+
+        let mut data = vec![0; (frames * u64::from(self.info.bpf())) as usize];
+        if let Err(err) = self.reader.read_exact(&mut data) {
+            eprintln!("Failed to read samples: {}", err);
+            return None;
+        }
+        if let Some(ref order) = self.channel_order {
+            let sample_size = (self.info.bpf() / self.info.channels()) as usize;
+            data = wav::reorder_channels(&data, sample_size, order);
+        }
+
+        let rate = self.info.rate();
+        let pts = audio_config::samples_to_time(self.position, rate);
+        let duration = audio_config::samples_to_time(self.position + frames, rate) - pts;
+        self.position += frames;
+
+        let mut buffer = gst::Buffer::from_mut_slice(data);
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(pts);
+            buffer.set_duration(duration);
+            buffer.set_offset(self.position - frames);
+        }
+        Some(buffer)
+    }
+
+    // Moves to the frame at that time, in nanoseconds (past the end means at the end)
+    pub fn seek(&mut self, time: u64) -> bool {
+        let frame = u128::from(time) * u128::from(self.info.rate())
+            / u128::from(gst::ClockTime::SECOND.nseconds());
+        let frame = (frame as u64).min(self.frames);
+
+        let offset = self.data_offset + frame * u64::from(self.info.bpf());
+        match self.reader.seek(SeekFrom::Start(offset)) {
+            Ok(_) => {
+                self.position = frame;
+                true
+            }
+            Err(err) => {
+                eprintln!("Failed to seek: {}", err);
+                false
+            }
+        }
+    }
+}
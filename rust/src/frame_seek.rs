@@ -0,0 +1,434 @@
+#![allow(dead_code)]
+
+use gst::prelude::*;
+use gst::{SeekFlags, SeekType};
+
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// Seeking to a frame instead of a time.
+// A frame number (or a SMPTE timecode, which is just a frame number written in hours, minutes,
+// seconds and frames) is turned into a time with the framerate the video sink negotiated,
+// then we seek there with ACCURATE so the decoder drops everything before that exact frame.
+// To check the result we look at the PTS of the first buffer that reaches the video sink after the flush.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameTarget {
+    Frame(u64),
+    Timecode {
+        hours: u64,
+        minutes: u64,
+        seconds: u64,
+        frames: u64,
+        /// "HH:MM:SS;FF" is drop-frame timecode (29.97 and 59.94 fps)
+        drop_frame: bool,
+    },
+}
+
+impl FrameTarget {
+    // "--seek-frame <number>" or "--seek-timecode <HH:MM:SS:FF>"
+    pub fn from_args(args: &[String]) -> Option<Result<FrameTarget, String>> {
+        let value_of = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .map(|index| args.get(index + 1).map(|value| value.as_str()))
+        };
+
+        if let Some(value) = value_of("--seek-frame") {
+            let value = value.unwrap_or("");
+            Some(
+                value
+                    .parse::<u64>()
+                    .map(FrameTarget::Frame)
+                    .map_err(|_| format!("invalid frame number '{}'", value)),
+            )
+        } else {
+            value_of("--seek-timecode")
+                .map(|value| FrameTarget::parse_timecode(value.unwrap_or("")))
+        }
+    }
+
+    pub fn parse_timecode(text: &str) -> Result<FrameTarget, String> {
+        let invalid = || format!("invalid timecode '{}', expected HH:MM:SS:FF", text);
+
+        let drop_frame = text.contains(';');
+        let parts = text
+            .split(|c| c == ':' || c == ';')
+            .map(|part| part.parse::<u64>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match parts[..] {
+            [hours, minutes, seconds, frames] if minutes < 60 && seconds < 60 => {
+                Ok(FrameTarget::Timecode {
+                    hours,
+                    minutes,
+                    seconds,
+                    frames,
+                    drop_frame,
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    // The frame number this target designates at the given framerate, if the timecode is valid
+    // at that framerate
+    pub fn frame_number(&self, framerate: gst::Fraction) -> Result<u64, String> {
+        match *self {
+            FrameTarget::Frame(frame) => Ok(frame),
+            FrameTarget::Timecode {
+                hours,
+                minutes,
+                seconds,
+                frames,
+                drop_frame,
+            } => {
+                // Timecodes count in whole frames per second, 30 for 29.97 fps
+                let fps =
+                    (f64::from(*framerate.numer()) / f64::from(*framerate.denom())).round() as u64;
+                if frames >= fps {
+                    return Err(format!(
+                        "frame {} of the timecode is past the {} frames of a second",
+                        frames, fps
+                    ));
+                }
+                let mut frame = hours
+                    .checked_mul(3600)
+                    .and_then(|total| total.checked_add(minutes * 60 + seconds))
+                    .and_then(|total| total.checked_mul(fps))
+                    .and_then(|total| total.checked_add(frames))
+                    .ok_or_else(|| format!("the timecode is past the last frame at {} fps", fps))?;
+
+                // Drop-frame skips the first frame numbers of every minute, except every tenth
+                // minute. It only exists for 29.97 and 59.94 fps, which drop 2 and 4 numbers.
+                if drop_frame {
+                    let rate = (*framerate.numer(), *framerate.denom());
+                    if rate != (30000, 1001) && rate != (60000, 1001) {
+                        return Err(format!(
+                            "drop-frame timecodes need 30000/1001 or 60000/1001 fps, not {}/{}",
+                            rate.0, rate.1
+                        ));
+                    }
+                    let dropped_per_minute = fps / 15;
+                    if seconds == 0 && frames < dropped_per_minute && minutes % 10 != 0 {
+                        return Err(format!(
+                            "frame {} doesn't exist at minute {} in drop-frame timecode",
+                            frames, minutes
+                        ));
+                    }
+                    let total_minutes = hours * 60 + minutes;
+                    frame -= dropped_per_minute * (total_minutes - total_minutes / 10);
+                }
+
+                Ok(frame)
+            }
+        }
+    }
+}
+
+// The time of the start of a frame, None if it doesn't fit in a ClockTime.
+// Like for audio, durations are differences of these.
+pub fn frame_to_time(frame: u64, framerate: gst::Fraction) -> Option<gst::ClockTime> {
+    let numer = *framerate.numer() as u128;
+    let denom = *framerate.denom() as u128 * gst::ClockTime::SECOND.nseconds() as u128;
+    if numer == 0 {
+        return None;
+    }
+    u64::try_from(frame as u128 * denom / numer)
+        .ok()
+        .filter(|&nseconds| nseconds != u64::MAX)
+        .map(gst::ClockTime::from_nseconds)
+}
+
+// The frame shown at the given time, rounded to the nearest one
+pub fn time_to_frame(time: gst::ClockTime, framerate: gst::Fraction) -> u64 {
+    let numer = *framerate.numer() as u128;
+    let denom = *framerate.denom() as u128 * gst::ClockTime::SECOND.nseconds() as u128;
+    ((time.nseconds() as u128 * numer + denom / 2) / denom) as u64
+}
+
+// The sink pad of the element actually rendering the video inside playbin
+// (e.g. the xvimagesink that autovideosink plugged), once the pipeline is prerolled
+pub fn video_sink_pad(pipeline: &gst::Element) -> Option<gst::Pad> {
+    let bin = pipeline.downcast_ref::<gst::Bin>()?;
+    let mut iter = bin.iterate_recurse();
+
+    while let Ok(Some(element)) = iter.next() {
+        if element.is::<gst::Bin>() {
+            continue;
+        }
+
+        let is_video_sink = element
+            .factory()
+            .and_then(|factory| factory.metadata("klass").map(|klass| klass.to_string()))
+            .map(|klass| klass.contains("Sink") && klass.contains("Video"))
+            .unwrap_or(false);
+        if is_video_sink {
+            return element.static_pad("sink");
+        }
+    }
+
+    None
+}
+
+pub fn negotiated_framerate(pad: &gst::Pad) -> Option<gst::Fraction> {
+    let caps = pad.current_caps()?;
+    let framerate = caps.structure(0)?.get::<gst::Fraction>("framerate").ok()?;
+
+    // Variable framerate streams have 0/1 and can't be seeked by frame
+    if *framerate.numer() == 0 {
+        None
+    } else {
+        Some(framerate)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FirstBuffer {
+    /// PTS of the buffer, converted to stream time
+    pub stream_time: Option<gst::ClockTime>,
+    /// When it reached the pad
+    pub arrived: Instant,
+}
+
+#[derive(Default)]
+struct WatchState {
+    flushed: bool,
+    segment: Option<gst::FormattedSegment<gst::ClockTime>>,
+    first_buffer: Option<FirstBuffer>,
+}
+
+// Catches the first buffer flowing through a pad after the next flush.
+// Buffers that were already on their way before the flush are ignored.
+pub struct FirstBufferWatch {
+    state: Arc<Mutex<WatchState>>,
+}
+
+impl FirstBufferWatch {
+    pub fn arm(pad: &gst::Pad) -> FirstBufferWatch {
+        let state = Arc::new(Mutex::new(WatchState::default()));
+        let state_clone = state.clone();
+
+        pad.add_probe(
+            gst::PadProbeType::BUFFER
+                | gst::PadProbeType::EVENT_DOWNSTREAM
+                | gst::PadProbeType::EVENT_FLUSH,
+            move |_, info| {
+                let mut state = state_clone.lock().unwrap();
+                match info.data {
+                    Some(gst::PadProbeData::Event(ref event)) => match event.view() {
+                        gst::EventView::FlushStop(..) => state.flushed = true,
+                        gst::EventView::Segment(ev) => {
+                            state.segment = ev.segment().downcast_ref::<gst::ClockTime>().cloned()
+                        }
+                        _ => (),
+                    },
+                    Some(gst::PadProbeData::Buffer(ref buffer)) if state.flushed => {
+                        let stream_time = state
+                            .segment
+                            .as_ref()
+                            .and_then(|segment| segment.to_stream_time(buffer.pts()));
+                        state.first_buffer = Some(FirstBuffer {
+                            stream_time,
+                            arrived: Instant::now(),
+                        });
+                        return gst::PadProbeReturn::Remove;
+                    }
+                    _ => (),
+                }
+                gst::PadProbeReturn::Ok
+            },
+        );
+
+        FirstBufferWatch { state }
+    }
+
+    pub fn first_buffer(&self) -> Option<FirstBuffer> {
+        self.state.lock().unwrap().first_buffer
+    }
+}
+
+#[derive(Debug)]
+pub struct FrameSeekResult {
+    pub framerate: gst::Fraction,
+    pub requested_frame: u64,
+    pub requested_time: gst::ClockTime,
+    /// Stream time of the first frame shown after the seek
+    pub actual_time: Option<gst::ClockTime>,
+}
+
+impl FrameSeekResult {
+    pub fn actual_frame(&self) -> Option<u64> {
+        self.actual_time
+            .map(|time| time_to_frame(time, self.framerate))
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.actual_frame() == Some(self.requested_frame)
+    }
+}
+
+// Prerolls the pipeline, seeks to the target frame and reports which frame was shown.
+// The pipeline is left PAUSED on that frame.
+pub fn seek_to_frame(
+    pipeline: &gst::Element,
+    target: FrameTarget,
+) -> Result<FrameSeekResult, String> {
+    // The framerate is only known once the video sink has negotiated its caps
+    let _ = pipeline.set_state(gst::State::Paused);
+    if !wait_for_preroll(pipeline) {
+        return Err("the pipeline failed to preroll".to_string());
+    }
+
+    let pad = video_sink_pad(pipeline).ok_or("no video sink in the pipeline")?;
+    let framerate = negotiated_framerate(&pad).ok_or("the video has no fixed framerate")?;
+
+    let requested_frame = target.frame_number(framerate)?;
+    let requested_time = frame_to_time(requested_frame, framerate).ok_or("frame out of range")?;
+
+    let watch = FirstBufferWatch::arm(&pad);
+    pipeline
+        .seek(
+            1.0,
+            SeekFlags::FLUSH | SeekFlags::ACCURATE,
+            SeekType::Set,
+            requested_time,
+            SeekType::None,
+            gst::ClockTime::ZERO,
+        )
+        .map_err(|_| "seek was not handled".to_string())?;
+
+    // In PAUSED the pipeline prerolls again on the new frame before the state change completes
+    if !wait_for_preroll(pipeline) {
+        return Err("the pipeline failed to preroll after the seek".to_string());
+    }
+
+    Ok(FrameSeekResult {
+        framerate,
+        requested_frame,
+        requested_time,
+        actual_time: watch.first_buffer().and_then(|buffer| buffer.stream_time),
+    })
+}
+
+pub fn wait_for_preroll(pipeline: &gst::Element) -> bool {
+    matches!(
+        pipeline.state(10 * gst::ClockTime::SECOND).0,
+        Ok(gst::StateChangeSuccess::Success) | Ok(gst::StateChangeSuccess::NoPreroll)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timecode(text: &str) -> FrameTarget {
+        FrameTarget::parse_timecode(text).unwrap()
+    }
+
+    #[test]
+    fn parses_timecodes() {
+        assert_eq!(
+            timecode("01:02:03:04"),
+            FrameTarget::Timecode {
+                hours: 1,
+                minutes: 2,
+                seconds: 3,
+                frames: 4,
+                drop_frame: false,
+            }
+        );
+        assert_eq!(
+            timecode("00:01:00;02"),
+            FrameTarget::Timecode {
+                hours: 0,
+                minutes: 1,
+                seconds: 0,
+                frames: 2,
+                drop_frame: true,
+            }
+        );
+
+        for text in [
+            "",
+            "1:2:3",
+            "00:00:00:00:00",
+            "00:60:00:00",
+            "00:00:60:00",
+            "aa:00:00:00",
+        ] {
+            assert!(FrameTarget::parse_timecode(text).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn non_drop_frame_numbers() {
+        let pal = gst::Fraction::new(25, 1);
+        assert_eq!(timecode("00:00:00:00").frame_number(pal), Ok(0));
+        assert_eq!(timecode("00:00:01:24").frame_number(pal), Ok(49));
+        assert_eq!(timecode("01:00:00:00").frame_number(pal), Ok(90_000));
+        assert_eq!(FrameTarget::Frame(1234).frame_number(pal), Ok(1234));
+
+        // Counted at 30 frames a second at 29.97 fps without drop-frame
+        let ntsc = gst::Fraction::new(30000, 1001);
+        assert_eq!(timecode("00:01:00:00").frame_number(ntsc), Ok(1800));
+    }
+
+    #[test]
+    fn drop_frame_numbers() {
+        let ntsc = gst::Fraction::new(30000, 1001);
+        // 00:00:59;29 is followed by 00:01:00;02
+        assert_eq!(timecode("00:00:59;29").frame_number(ntsc), Ok(1799));
+        assert_eq!(timecode("00:01:00;02").frame_number(ntsc), Ok(1800));
+        // Nothing is dropped at every tenth minute
+        assert_eq!(timecode("00:10:00;00").frame_number(ntsc), Ok(17_982));
+        assert_eq!(timecode("01:00:00;00").frame_number(ntsc), Ok(107_892));
+
+        let ntsc_double = gst::Fraction::new(60000, 1001);
+        assert_eq!(timecode("00:01:00;04").frame_number(ntsc_double), Ok(3600));
+    }
+
+    #[test]
+    fn rejects_invalid_timecodes() {
+        let pal = gst::Fraction::new(25, 1);
+        let ntsc = gst::Fraction::new(30000, 1001);
+        // Only 25 frames a second
+        assert!(timecode("00:00:01:25").frame_number(pal).is_err());
+        assert!(timecode("00:00:01:45").frame_number(pal).is_err());
+        // Drop-frame at other framerates
+        assert!(timecode("00:00:01;00").frame_number(pal).is_err());
+        assert!(timecode("00:00:01;00")
+            .frame_number(gst::Fraction::new(30, 1))
+            .is_err());
+        // Dropped frame numbers
+        assert!(timecode("00:01:00;00").frame_number(ntsc).is_err());
+        assert!(timecode("00:01:00;01").frame_number(ntsc).is_err());
+        // Too many hours for a frame number
+        assert!(timecode("999999999999999:00:00:00")
+            .frame_number(pal)
+            .is_err());
+    }
+
+    #[test]
+    fn frames_and_times() {
+        let ntsc = gst::Fraction::new(30000, 1001);
+        let time = frame_to_time(1800, ntsc).unwrap();
+        assert_eq!(time, gst::ClockTime::from_nseconds(60_060_000_000));
+        assert_eq!(time_to_frame(time, ntsc), 1800);
+
+        let pal = gst::Fraction::new(25, 1);
+        assert_eq!(time_to_frame(gst::ClockTime::from_mseconds(19), pal), 0);
+        assert_eq!(time_to_frame(gst::ClockTime::from_mseconds(20), pal), 1);
+        assert_eq!(time_to_frame(gst::ClockTime::from_mseconds(59), pal), 1);
+    }
+
+    #[test]
+    fn large_frame_numbers() {
+        // About 30 fps, with a denominator so large that frame * denom doesn't fit in 64 bits
+        let framerate = gst::Fraction::new(2_000_000_000, 66_666_667);
+        let frame = u64::MAX / 66_666_667 + 1;
+        let time = frame_to_time(frame, framerate).unwrap();
+        assert_eq!(time_to_frame(time, framerate), frame);
+        assert_eq!(frame_to_time(u64::MAX, framerate), None);
+    }
+}
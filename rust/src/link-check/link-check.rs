@@ -0,0 +1,93 @@
+use gst::prelude::*;
+
+use std::env;
+
+#[path = "../caps_compat.rs"]
+mod caps_compat;
+#[path = "../element_info.rs"]
+mod element_info;
+
+use element_info::print_caps;
+
+// Tells whether an element made by one factory can ever link to an element made by another one.
+// USAGE: link-check <src factory> <sink factory>
+//
+// Like chapter-6, we only look at the pad templates: the src templates of the first factory are
+// intersected with the sink templates of the second one. When nothing is in common, we show which
+// fields conflict and which elements of the registry could be put in between.
+
+// How many bridging elements we suggest at most
+const MAX_SUGGESTIONS: usize = 10;
+
+fn check_link(src_factory: &gst::ElementFactory, sink_factory: &gst::ElementFactory) {
+    let src_caps = caps_compat::template_caps(src_factory, gst::PadDirection::Src);
+    let sink_caps = caps_compat::template_caps(sink_factory, gst::PadDirection::Sink);
+
+    if src_caps.is_empty() {
+        println!("{} has no src pad template", src_factory.name());
+        return;
+    }
+    if sink_caps.is_empty() {
+        println!("{} has no sink pad template", sink_factory.name());
+        return;
+    }
+
+    println!(
+        "===== {} ! {} =====",
+        src_factory.name(),
+        sink_factory.name()
+    );
+
+    let common = src_caps.intersect(&sink_caps);
+    if !common.is_empty() {
+        println!("Compatible, common caps:");
+        print_caps(&common, "  ");
+        return;
+    }
+
+    println!("Not compatible:");
+    for conflict in caps_compat::conflicts(&src_caps, &sink_caps) {
+        println!("  {}", conflict);
+    }
+
+    let bridges = caps_compat::bridging_factories(&src_caps, &sink_caps);
+    if bridges.is_empty() {
+        println!("No single element can convert between them (try caps-path)");
+        return;
+    }
+
+    println!("Elements that could go in between:");
+    for factory in bridges.iter().take(MAX_SUGGESTIONS) {
+        println!(
+            "  {:<24} rank {:<10} {}",
+            factory.name(),
+            format!("{:?}", factory.rank()),
+            factory.metadata("klass").unwrap_or("")
+        );
+    }
+}
+
+fn main() {
+    if let Err(err) = gst::init() {
+        eprintln!("Failed to initialize Gst: {}", err);
+        return;
+    }
+
+    let args: Vec<_> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("USAGE: link-check <src factory> <sink factory>");
+        return;
+    }
+
+    let find = |name: &str| {
+        let factory = gst::ElementFactory::find(name);
+        if factory.is_none() {
+            eprintln!("No such element factory: {}", name);
+        }
+        factory
+    };
+
+    if let (Some(src_factory), Some(sink_factory)) = (find(&args[1]), find(&args[2])) {
+        check_link(&src_factory, &sink_factory);
+    }
+}
@@ -0,0 +1,86 @@
+#![allow(dead_code)]
+
+use gst::prelude::*;
+use serde_json::json;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+// Writes the playback progress of a pipeline as NDJSON, one JSON object per line, e.g.
+// {"position":12000000000,"duration":52209000000,"rate":1.0,"buffering":100,"state":"Playing"}
+// Times are in nanoseconds and are null while they can't be queried (e.g. before PAUSED).
+// The lines go to stdout with "--progress" (or "--progress=-") or to a file with
+// "--progress=<path>". On stdout, chapter-4 prints its own messages to stderr so they don't mix.
+pub struct ProgressReporter {
+    out: Box<dyn Write>,
+    /// Are the lines going to stdout?
+    stdout: bool,
+    /// Last buffering level seen on the bus, in percent
+    buffering: i32,
+}
+
+impl ProgressReporter {
+    pub fn to_file(path: &str) -> io::Result<ProgressReporter> {
+        let file = File::create(path)?;
+        Ok(ProgressReporter::new(Box::new(BufWriter::new(file)), false))
+    }
+
+    pub fn to_stdout() -> ProgressReporter {
+        ProgressReporter::new(Box::new(io::stdout()), true)
+    }
+
+    fn new(out: Box<dyn Write>, stdout: bool) -> ProgressReporter {
+        ProgressReporter {
+            out,
+            stdout,
+            // Nothing is buffering until the pipeline tells us otherwise
+            buffering: 100,
+        }
+    }
+
+    // "--progress" or "--progress=-" reports to stdout, "--progress=<path>" to that file
+    pub fn from_args(args: &[String]) -> Option<io::Result<ProgressReporter>> {
+        args.iter().find_map(|arg| match arg.as_str() {
+            "--progress" | "--progress=-" => Some(Ok(ProgressReporter::to_stdout())),
+            _ => arg
+                .strip_prefix("--progress=")
+                .map(ProgressReporter::to_file),
+        })
+    }
+
+    pub fn is_stdout(&self) -> bool {
+        self.stdout
+    }
+
+    // Must be given every bus message so the buffering level stays up to date
+    pub fn handle_message(&mut self, msg: &gst::Message) {
+        if let gst::MessageView::Buffering(buffering) = msg.view() {
+            self.buffering = buffering.percent();
+        }
+    }
+
+    pub fn report(&mut self, element: &gst::Element) -> io::Result<()> {
+        let position = element.query_position::<gst::ClockTime>();
+        let duration = element.query_duration::<gst::ClockTime>();
+
+        // The current rate is the one of the segment the pipeline is playing
+        let mut segment = gst::query::Segment::new(gst::Format::Time);
+        let rate = if element.query(&mut segment) {
+            let (rate, _, _) = segment.result();
+            Some(rate)
+        } else {
+            None
+        };
+
+        let line = json!({
+            "position": position.map(|p| p.nseconds()),
+            "duration": duration.map(|d| d.nseconds()),
+            "rate": rate,
+            "buffering": self.buffering,
+            "state": format!("{:?}", element.current_state()),
+        });
+
+        writeln!(self.out, "{}", line)?;
+        self.out.flush()
+    }
+}
@@ -0,0 +1,216 @@
+#![allow(dead_code)]
+
+use gst::prelude::*;
+
+use std::sync::{Arc, Mutex};
+
+use crate::clock_time;
+
+// Queue settings and statistics for the branches of a tee.
+// The tee pushes every buffer to all its branches in turn, so a branch whose queue is full blocks
+// the others. Limiting the queue and making it leaky ("downstream" drops the oldest buffers,
+// "upstream" the new ones) keeps a slow branch from stalling the whole tee, at the cost of
+// dropping data in that branch only.
+//
+// A policy is given as "--queue <queue name>:<setting>=<value>,..." with the settings
+// max-buffers, max-bytes, max-time (e.g. 500ms) and leaky (no, upstream or downstream).
+// "--queue-stats <seconds>" prints the levels and counters of every queue periodically.
+
+#[derive(Debug, Clone, Default)]
+pub struct QueuePolicy {
+    pub queue: String,
+    pub max_buffers: Option<u32>,
+    pub max_bytes: Option<u32>,
+    pub max_time: Option<gst::ClockTime>,
+    pub leaky: Option<String>,
+}
+
+impl QueuePolicy {
+    pub fn parse(text: &str) -> Result<QueuePolicy, String> {
+        let (queue, settings) = text.split_once(':').ok_or_else(|| {
+            format!(
+                "invalid queue policy '{}', expected <queue>:<settings>",
+                text
+            )
+        })?;
+
+        let mut policy = QueuePolicy {
+            queue: queue.to_string(),
+            ..Default::default()
+        };
+        for setting in settings.split(',') {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("invalid queue setting '{}'", setting))?;
+            let invalid = || format!("invalid value for {}: {}", key, value);
+
+            match key {
+                "max-buffers" => policy.max_buffers = Some(value.parse().map_err(|_| invalid())?),
+                "max-bytes" => policy.max_bytes = Some(value.parse().map_err(|_| invalid())?),
+                "max-time" => {
+                    policy.max_time = Some(clock_time::parse_time(value).ok_or_else(invalid)?)
+                }
+                "leaky" => match value {
+                    "no" | "upstream" | "downstream" => policy.leaky = Some(value.to_string()),
+                    _ => return Err(invalid()),
+                },
+                _ => return Err(format!("unknown queue setting {}", key)),
+            }
+        }
+
+        Ok(policy)
+    }
+
+    // Every "--queue <policy>" of the arguments
+    pub fn from_args(args: &[String]) -> Result<Vec<QueuePolicy>, String> {
+        args.iter()
+            .zip(args.iter().skip(1))
+            .filter(|(arg, _)| *arg == "--queue")
+            .map(|(_, policy)| QueuePolicy::parse(policy))
+            .collect()
+    }
+
+    // A limit of 0 disables it, so max-time=0 with max-buffers=10 limits by buffer count only
+    pub fn apply(&self, queue: &gst::Element) -> Result<(), glib::BoolError> {
+        if let Some(max_buffers) = self.max_buffers {
+            queue.set_property("max-size-buffers", max_buffers)?;
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            queue.set_property("max-size-bytes", max_bytes)?;
+        }
+        if let Some(max_time) = self.max_time {
+            queue.set_property("max-size-time", max_time.nseconds())?;
+        }
+        if let Some(ref leaky) = self.leaky {
+            queue.set_property_from_str("leaky", leaky);
+        }
+        Ok(())
+    }
+}
+
+// Applies each policy to the queue of that name in the pipeline
+pub fn apply_policies(pipeline: &gst::Bin, policies: &[QueuePolicy]) -> Result<(), String> {
+    for policy in policies {
+        let queue = pipeline
+            .by_name(&policy.queue)
+            .ok_or_else(|| format!("no queue named {}", policy.queue))?;
+        policy.apply(&queue).map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+// The seconds between two reports of "--queue-stats <seconds>", if given
+pub fn report_interval(args: &[String]) -> Option<u32> {
+    let i = args.iter().position(|arg| arg == "--queue-stats")?;
+    args.get(i + 1)
+        .and_then(|seconds| seconds.parse().ok())
+        .filter(|&seconds| seconds > 0)
+}
+
+fn is_queue(element: &gst::Element) -> bool {
+    element
+        .factory()
+        .map(|factory| factory.name() == "queue")
+        .unwrap_or(false)
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    /// Times the queue got full, blocking (or dropping, if leaky) upstream
+    overruns: u64,
+    /// Times the queue ran empty, starving downstream
+    underruns: u64,
+}
+
+struct WatchedQueue {
+    queue: gst::Element,
+    counters: Arc<Mutex<Counters>>,
+}
+
+#[derive(Clone, Default)]
+pub struct QueueStats {
+    queues: Arc<Mutex<Vec<WatchedQueue>>>,
+}
+
+impl QueueStats {
+    pub fn new() -> QueueStats {
+        QueueStats::default()
+    }
+
+    // Counts the overrun and underrun signals of the queue
+    pub fn watch(&self, queue: &gst::Element) -> Result<(), glib::BoolError> {
+        let counters = Arc::new(Mutex::new(Counters::default()));
+
+        let counters_clone = counters.clone();
+        queue.connect("overrun", false, move |_| {
+            counters_clone.lock().unwrap().overruns += 1;
+            None
+        })?;
+        let counters_clone = counters.clone();
+        queue.connect("underrun", false, move |_| {
+            counters_clone.lock().unwrap().underruns += 1;
+            None
+        })?;
+
+        self.queues.lock().unwrap().push(WatchedQueue {
+            queue: queue.clone(),
+            counters,
+        });
+        Ok(())
+    }
+
+    // Watches every queue in the pipeline, including the ones added later on
+    // (decodebin and friends create theirs inside nested bins, hence deep-element-added)
+    pub fn watch_all(&self, pipeline: &gst::Bin) -> Result<(), glib::BoolError> {
+        let mut iter = pipeline.iterate_recurse();
+        while let Ok(Some(element)) = iter.next() {
+            if is_queue(&element) {
+                self.watch(&element)?;
+            }
+        }
+
+        let stats = self.clone();
+        pipeline.connect_deep_element_added(move |_, _, element| {
+            if is_queue(element) {
+                if let Err(err) = stats.watch(element) {
+                    eprintln!("Failed to watch queue {}: {}", element.name(), err);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    // One line per queue: how full it is right now and how often it was full or empty so far
+    pub fn report(&self) {
+        fn level(queue: &gst::Element, name: &str) -> u64 {
+            queue
+                .property(name)
+                .ok()
+                .and_then(|value| {
+                    value
+                        .get::<u32>()
+                        .map(u64::from)
+                        .or_else(|_| value.get::<u64>())
+                        .ok()
+                })
+                .unwrap_or(0)
+        }
+
+        for watched in self.queues.lock().unwrap().iter() {
+            let queue = &watched.queue;
+            let counters = watched.counters.lock().unwrap();
+            println!(
+                "{}: {}/{} buffers, {}/{} bytes, {}/{} | overruns {}, underruns {}",
+                queue.name(),
+                level(queue, "current-level-buffers"),
+                level(queue, "max-size-buffers"),
+                level(queue, "current-level-bytes"),
+                level(queue, "max-size-bytes"),
+                gst::ClockTime::from_nseconds(level(queue, "current-level-time")),
+                gst::ClockTime::from_nseconds(level(queue, "max-size-time")),
+                counters.overruns,
+                counters.underruns
+            );
+        }
+    }
+}
@@ -0,0 +1,215 @@
+#![allow(dead_code)]
+
+use gst::prelude::*;
+use gst::{SeekFlags, SeekType};
+
+use std::time::{Duration, Instant};
+
+use crate::clock_time::diff_ns;
+use crate::frame_seek::{self, FirstBufferWatch};
+
+// Measures how long seeks take and how close they land to the requested position.
+// For every seek we note the wall time until ASYNC_DONE (the pipeline prerolled again) and until
+// the first buffer reached the video sink, and compare the requested position with the one we got.
+// Seeks are done in PAUSED so that playback itself doesn't move the position we measure.
+
+// The flag combinations we compare, FLUSH is always added
+pub fn flag_combinations() -> Vec<(&'static str, SeekFlags)> {
+    vec![
+        ("KEY_UNIT", SeekFlags::KEY_UNIT),
+        ("ACCURATE", SeekFlags::ACCURATE),
+        (
+            "KEY_UNIT|SNAP_BEFORE",
+            SeekFlags::KEY_UNIT | SeekFlags::SNAP_BEFORE,
+        ),
+        (
+            "KEY_UNIT|SNAP_AFTER",
+            SeekFlags::KEY_UNIT | SeekFlags::SNAP_AFTER,
+        ),
+        (
+            "KEY_UNIT|SNAP_NEAREST",
+            SeekFlags::KEY_UNIT | SeekFlags::SNAP_NEAREST,
+        ),
+    ]
+}
+
+// Where we seek to, as fractions of the duration
+const TARGETS: &[f64] = &[0.13, 0.37, 0.61, 0.29, 0.83];
+
+#[derive(Debug, Clone)]
+pub struct SeekMeasurement {
+    pub flags_name: &'static str,
+    pub requested: gst::ClockTime,
+    pub actual: Option<gst::ClockTime>,
+    /// Wall time from sending the seek to ASYNC_DONE
+    pub async_done: Option<Duration>,
+    /// Wall time from sending the seek to the first buffer at the video sink
+    pub first_buffer: Option<Duration>,
+}
+
+impl SeekMeasurement {
+    // actual - requested, in milliseconds
+    pub fn error_ms(&self) -> Option<f64> {
+        self.actual
+            .map(|actual| diff_ns(actual, self.requested) as f64 / 1e6)
+    }
+}
+
+pub fn measure_seeks(pipeline: &gst::Element) -> Result<Vec<SeekMeasurement>, String> {
+    let bus = pipeline.bus().ok_or("pipeline without bus")?;
+
+    let _ = pipeline.set_state(gst::State::Paused);
+    if !frame_seek::wait_for_preroll(pipeline) {
+        return Err("the pipeline failed to preroll".to_string());
+    }
+
+    let duration = pipeline
+        .query_duration::<gst::ClockTime>()
+        .ok_or("unknown duration")?;
+    // Audio only streams have no video sink, we then only measure ASYNC_DONE
+    let video_pad = frame_seek::video_sink_pad(pipeline);
+
+    let mut measurements = Vec::new();
+    for (flags_name, flags) in flag_combinations() {
+        for target in TARGETS {
+            let requested =
+                gst::ClockTime::from_nseconds((duration.nseconds() as f64 * target) as u64);
+
+            // Forget about the ASYNC_DONE of the previous seek, one message at a time so an
+            // error or the EOS posted since then isn't dropped with it
+            while let Some(msg) = bus.pop() {
+                check_message(&msg, flags_name)?;
+            }
+
+            let watch = video_pad.as_ref().map(FirstBufferWatch::arm);
+            let sent = Instant::now();
+            pipeline
+                .seek(
+                    1.0,
+                    SeekFlags::FLUSH | flags,
+                    SeekType::Set,
+                    requested,
+                    SeekType::None,
+                    gst::ClockTime::ZERO,
+                )
+                .map_err(|_| format!("{} seek was not handled", flags_name))?;
+
+            let msg = bus.timed_pop_filtered(
+                10 * gst::ClockTime::SECOND,
+                &[
+                    gst::MessageType::AsyncDone,
+                    gst::MessageType::Error,
+                    gst::MessageType::Eos,
+                ],
+            );
+            let async_done = match msg {
+                Some(msg) if msg.type_() == gst::MessageType::AsyncDone => Some(sent.elapsed()),
+                Some(msg) => {
+                    check_message(&msg, flags_name)?;
+                    None
+                }
+                None => None,
+            };
+
+            let first_buffer = watch.as_ref().and_then(|watch| watch.first_buffer());
+            // The first buffer tells where we really landed, the position query is the fallback
+            let actual = first_buffer
+                .and_then(|buffer| buffer.stream_time)
+                .or_else(|| pipeline.query_position::<gst::ClockTime>());
+
+            measurements.push(SeekMeasurement {
+                flags_name,
+                requested,
+                actual,
+                async_done,
+                first_buffer: first_buffer.map(|buffer| buffer.arrived.duration_since(sent)),
+            });
+        }
+    }
+
+    Ok(measurements)
+}
+
+// An error or the EOS ends the measurement, instead of showing up as timeouts
+fn check_message(msg: &gst::Message, flags_name: &str) -> Result<(), String> {
+    match msg.view() {
+        gst::MessageView::Error(err) => {
+            Err(format!("error during {} seek: {}", flags_name, err.error()))
+        }
+        gst::MessageView::Eos(..) => Err(format!("end of stream during {} seek", flags_name)),
+        _ => Ok(()),
+    }
+}
+
+fn average(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+    if count == 0 {
+        None
+    } else {
+        Some(sum / count as f64)
+    }
+}
+
+fn format_ms(value: Option<f64>) -> String {
+    value
+        .map(|value| format!("{:.1}", value))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+pub fn print_measurements(measurements: &[SeekMeasurement]) {
+    println!("===== seeks =====");
+    println!(
+        "{:<22} {:>14} {:>14} {:>12} {:>12} {:>10}",
+        "flags", "requested", "actual", "async ms", "buffer ms", "error ms"
+    );
+    for m in measurements {
+        println!(
+            "{:<22} {:>14} {:>14} {:>12} {:>12} {:>10}",
+            m.flags_name,
+            m.requested.to_string(),
+            m.actual.display().to_string(),
+            format_ms(m.async_done.map(|d| d.as_secs_f64() * 1000.0)),
+            format_ms(m.first_buffer.map(|d| d.as_secs_f64() * 1000.0)),
+            format_ms(m.error_ms()),
+        );
+    }
+
+    println!("===== summary =====");
+    println!(
+        "{:<22} {:>14} {:>14} {:>16} {:>16}",
+        "flags", "avg async ms", "avg buffer ms", "avg |error| ms", "max |error| ms"
+    );
+    for (flags_name, _) in flag_combinations() {
+        let rows: Vec<_> = measurements
+            .iter()
+            .filter(|m| m.flags_name == flags_name)
+            .collect();
+
+        let max_error = rows
+            .iter()
+            .filter_map(|m| m.error_ms())
+            .map(f64::abs)
+            .fold(None, |max: Option<f64>, error| {
+                Some(max.map_or(error, |max| max.max(error)))
+            });
+
+        println!(
+            "{:<22} {:>14} {:>14} {:>16} {:>16}",
+            flags_name,
+            format_ms(average(
+                rows.iter()
+                    .filter_map(|m| m.async_done)
+                    .map(|d| d.as_secs_f64() * 1000.0)
+            )),
+            format_ms(average(
+                rows.iter()
+                    .filter_map(|m| m.first_buffer)
+                    .map(|d| d.as_secs_f64() * 1000.0)
+            )),
+            format_ms(average(
+                rows.iter().filter_map(|m| m.error_ms()).map(f64::abs)
+            )),
+            format_ms(max_error),
+        );
+    }
+}
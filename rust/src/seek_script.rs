@@ -0,0 +1,467 @@
+#![allow(dead_code)]
+
+use gst::prelude::*;
+use gst::{SeekFlags, SeekType};
+
+use std::fs;
+use std::time::{Duration, Instant};
+
+use crate::clock_time::{diff_ns, parse_time};
+
+// A seek script is a text file with one action per line, executed in order against a playbin.
+// Empty lines and everything after a '#' are ignored.
+//
+//   play 10s                   play for 10 seconds (wall time)
+//   pause                      go to PAUSED and wait for it
+//   seek 30s flush,key-unit    seek to 30s with the given flags (default: flush)
+//   rate 2.0                   change the playback rate from the current position
+//   assert 30s 500ms           check that the position is 30s, give or take 500ms
+//
+// Flags are flush, accurate, key-unit, snap-before, snap-after, snap-nearest, segment and trickmode.
+
+// How long we wait for a seek or a state change to complete before failing the step
+const STEP_TIMEOUT_SECONDS: u64 = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Play(gst::ClockTime),
+    Pause,
+    Seek {
+        target: gst::ClockTime,
+        flags: SeekFlags,
+    },
+    Rate(f64),
+    AssertPosition {
+        expected: gst::ClockTime,
+        tolerance: gst::ClockTime,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Step {
+    /// Line of the script this step comes from (1-based)
+    pub line: usize,
+    /// The step as written in the script
+    pub text: String,
+    pub action: Action,
+}
+
+#[derive(Debug)]
+pub struct StepResult {
+    pub step: Step,
+    pub passed: bool,
+    /// What we observed, or why the step failed
+    pub detail: String,
+}
+
+pub fn parse_seek_flags(text: &str) -> Result<SeekFlags, String> {
+    let mut flags = SeekFlags::empty();
+
+    for name in text.split(',').filter(|name| !name.is_empty()) {
+        flags |= match name {
+            "flush" => SeekFlags::FLUSH,
+            "accurate" => SeekFlags::ACCURATE,
+            "key-unit" => SeekFlags::KEY_UNIT,
+            "snap-before" => SeekFlags::SNAP_BEFORE,
+            "snap-after" => SeekFlags::SNAP_AFTER,
+            "snap-nearest" => SeekFlags::SNAP_NEAREST,
+            "segment" => SeekFlags::SEGMENT,
+            "trickmode" => SeekFlags::TRICKMODE,
+            _ => return Err(format!("unknown seek flag '{}'", name)),
+        };
+    }
+
+    Ok(flags)
+}
+
+fn parse_line(line: &str) -> Result<Option<Action>, String> {
+    let line = line.split('#').next().unwrap_or("");
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    let time = |index: usize| -> Result<gst::ClockTime, String> {
+        let word = words
+            .get(index)
+            .ok_or_else(|| format!("'{}' needs a time", words[0]))?;
+        parse_time(word).ok_or_else(|| format!("invalid time '{}'", word))
+    };
+
+    let action = match words.first() {
+        None => return Ok(None),
+        Some(&"play") => Action::Play(time(1)?),
+        Some(&"pause") => Action::Pause,
+        Some(&"seek") => Action::Seek {
+            target: time(1)?,
+            flags: parse_seek_flags(words.get(2).unwrap_or(&"flush"))?,
+        },
+        Some(&"rate") => {
+            let rate = words
+                .get(1)
+                .and_then(|word| word.parse::<f64>().ok())
+                .filter(|rate| *rate != 0.0)
+                .ok_or("'rate' needs a non-zero number")?;
+            Action::Rate(rate)
+        }
+        Some(&"assert") => Action::AssertPosition {
+            expected: time(1)?,
+            tolerance: time(2)?,
+        },
+        Some(word) => return Err(format!("unknown action '{}'", word)),
+    };
+
+    Ok(Some(action))
+}
+
+pub fn parse_script(text: &str) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let action = parse_line(line).map_err(|err| format!("line {}: {}", index + 1, err))?;
+        if let Some(action) = action {
+            steps.push(Step {
+                line: index + 1,
+                text: line.trim().to_string(),
+                action,
+            });
+        }
+    }
+
+    Ok(steps)
+}
+
+pub fn load_script(path: &str) -> Result<Vec<Step>, String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+    parse_script(&text).map_err(|err| format!("{}: {}", path, err))
+}
+
+pub struct ScriptRunner {
+    playbin: gst::Element,
+    bus: gst::Bus,
+    /// Rate of the last seek, later seeks keep it
+    rate: f64,
+    /// Set once the pipeline posted an error, no step can succeed after that
+    broken: bool,
+    /// Set once the pipeline reached the end of the stream, which ends the script
+    ended: bool,
+}
+
+impl ScriptRunner {
+    pub fn new(playbin: &gst::Element) -> ScriptRunner {
+        ScriptRunner {
+            playbin: playbin.clone(),
+            bus: playbin.bus().expect("Pipeline without bus"),
+            rate: 1.0,
+            broken: false,
+            ended: false,
+        }
+    }
+
+    // Prerolls the pipeline and runs every step, stopping at the first one that breaks the pipeline
+    pub fn run(&mut self, steps: &[Step]) -> Vec<StepResult> {
+        let mut results = Vec::new();
+
+        // Seeks and position queries only work once the pipeline is prerolled
+        let _ = self.playbin.set_state(gst::State::Paused);
+        if let Err(err) = self.wait_for_state() {
+            eprintln!("Pipeline failed to preroll: {}", err);
+            return results;
+        }
+
+        for step in steps {
+            let result = match self.run_step(&step.action) {
+                Ok(detail) => StepResult {
+                    step: step.clone(),
+                    passed: true,
+                    detail,
+                },
+                Err(detail) => StepResult {
+                    step: step.clone(),
+                    passed: false,
+                    detail,
+                },
+            };
+
+            results.push(result);
+            if self.broken || self.ended {
+                break;
+            }
+        }
+
+        results
+    }
+
+    fn run_step(&mut self, action: &Action) -> Result<String, String> {
+        self.check_bus()?;
+
+        match *action {
+            Action::Play(duration) => self.play(duration),
+            Action::Pause => {
+                let _ = self.playbin.set_state(gst::State::Paused);
+                self.wait_for_state()?;
+                Ok("paused".to_string())
+            }
+            Action::Seek { target, flags } => {
+                self.seek(self.rate, flags, target)?;
+                Ok(format!("position {}", self.position()?))
+            }
+            Action::Rate(rate) => {
+                let position = self.position()?;
+                self.seek(rate, SeekFlags::FLUSH | SeekFlags::ACCURATE, position)?;
+                self.rate = rate;
+                Ok(format!("rate {} from {}", rate, position))
+            }
+            Action::AssertPosition {
+                expected,
+                tolerance,
+            } => {
+                let position = self.position()?;
+                let diff = diff_ns(position, expected);
+                let detail = format!("position {} ({:+.3} ms)", position, diff as f64 / 1e6);
+                if diff.unsigned_abs() <= tolerance.nseconds() {
+                    Ok(detail)
+                } else {
+                    Err(detail)
+                }
+            }
+        }
+    }
+
+    fn play(&mut self, duration: gst::ClockTime) -> Result<String, String> {
+        let _ = self.playbin.set_state(gst::State::Playing);
+
+        let deadline = Instant::now() + Duration::from_nanos(duration.nseconds());
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            let timeout = gst::ClockTime::from_nseconds(remaining.as_nanos() as u64);
+            let msg = self
+                .bus
+                .timed_pop_filtered(timeout, &[gst::MessageType::Error, gst::MessageType::Eos]);
+
+            match msg.as_ref().map(|msg| msg.view()) {
+                Some(gst::MessageView::Error(err)) => return Err(self.pipeline_error(err)),
+                Some(gst::MessageView::Eos(..)) => {
+                    self.ended = true;
+                    return Ok("end of stream reached".to_string());
+                }
+                _ => (),
+            }
+        }
+
+        Ok(format!("played until {}", self.position()?))
+    }
+
+    fn seek(
+        &mut self,
+        rate: f64,
+        flags: SeekFlags,
+        position: gst::ClockTime,
+    ) -> Result<(), String> {
+        // Old ASYNC_DONE messages (e.g. from the preroll) must not be taken for the one of this seek
+        self.check_bus()?;
+
+        // As in chapter-13, when going backwards the position is where the segment stops
+        let result = if rate > 0. {
+            self.playbin.seek(
+                rate,
+                flags,
+                SeekType::Set,
+                position,
+                SeekType::End,
+                gst::ClockTime::ZERO,
+            )
+        } else {
+            self.playbin.seek(
+                rate,
+                flags,
+                SeekType::Set,
+                gst::ClockTime::ZERO,
+                SeekType::Set,
+                position,
+            )
+        };
+        result.map_err(|_| "seek was not handled".to_string())?;
+
+        // Only flushing seeks make the pipeline preroll again
+        if flags.contains(SeekFlags::FLUSH) {
+            self.wait_for_async_done()?;
+        }
+
+        Ok(())
+    }
+
+    fn position(&self) -> Result<gst::ClockTime, String> {
+        self.playbin
+            .query_position::<gst::ClockTime>()
+            .ok_or_else(|| "could not query the position".to_string())
+    }
+
+    // Fails if something bad was posted on the bus since the last step, or if the end of the
+    // stream was reached (e.g. after a seek past the end). Every other message is dropped.
+    // (pop_filtered() would drop them too, but it doesn't stop at the first error)
+    fn check_bus(&mut self) -> Result<(), String> {
+        while let Some(msg) = self.bus.pop() {
+            self.check_message(&msg, "end of stream reached before this step")?;
+        }
+
+        Ok(())
+    }
+
+    fn check_message(&mut self, msg: &gst::Message, eos_detail: &str) -> Result<(), String> {
+        match msg.view() {
+            gst::MessageView::Error(err) => Err(self.pipeline_error(err)),
+            gst::MessageView::Eos(..) => {
+                self.ended = true;
+                Err(eos_detail.to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn wait_for_state(&self) -> Result<(), String> {
+        let (res, _, _) = self
+            .playbin
+            .state(gst::ClockTime::from_seconds(STEP_TIMEOUT_SECONDS));
+        match res {
+            Ok(gst::StateChangeSuccess::Async) => Err("state change timed out".to_string()),
+            Ok(_) => Ok(()),
+            Err(_) => Err("state change failed".to_string()),
+        }
+    }
+
+    fn wait_for_async_done(&mut self) -> Result<(), String> {
+        let msg = self.bus.timed_pop_filtered(
+            gst::ClockTime::from_seconds(STEP_TIMEOUT_SECONDS),
+            &[
+                gst::MessageType::AsyncDone,
+                gst::MessageType::Error,
+                gst::MessageType::Eos,
+            ],
+        );
+
+        match msg {
+            Some(msg) if msg.type_() == gst::MessageType::AsyncDone => Ok(()),
+            Some(msg) => self
+                .check_message(&msg, "end of stream reached during the seek")
+                .and(Err("seek did not complete".to_string())),
+            None => Err("seek did not complete".to_string()),
+        }
+    }
+
+    fn pipeline_error(&mut self, err: &gst::message::Error) -> String {
+        self.broken = true;
+        format!(
+            "error from {:?}: {}",
+            err.src().map(|s| s.path_string()),
+            err.error()
+        )
+    }
+}
+
+pub fn print_report(results: &[StepResult], total_steps: usize) {
+    println!("===== seek script report =====");
+    for result in results {
+        println!(
+            "{} line {:>3}: {:<30} {}",
+            if result.passed { "PASS" } else { "FAIL" },
+            result.step.line,
+            result.step.text,
+            result.detail
+        );
+    }
+
+    if results.len() < total_steps {
+        // The last step run tells why the others weren't
+        let reason = results
+            .last()
+            .map(|result| result.detail.as_str())
+            .unwrap_or("the pipeline failed to preroll");
+        println!("{} steps not run: {}", total_steps - results.len(), reason);
+    }
+
+    let passed = results.iter().filter(|result| result.passed).count();
+    println!("{}/{} steps passed", passed, total_steps);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_action() {
+        let script = "\
+# warm up
+play 2s
+pause
+
+seek 30s flush,key-unit   # jump
+rate -2
+assert 1:00 500ms
+";
+        let steps = parse_script(script).unwrap();
+        let actions: Vec<Action> = steps.iter().map(|step| step.action.clone()).collect();
+
+        assert_eq!(
+            actions,
+            vec![
+                Action::Play(gst::ClockTime::from_seconds(2)),
+                Action::Pause,
+                Action::Seek {
+                    target: gst::ClockTime::from_seconds(30),
+                    flags: SeekFlags::FLUSH | SeekFlags::KEY_UNIT,
+                },
+                Action::Rate(-2.0),
+                Action::AssertPosition {
+                    expected: gst::ClockTime::from_seconds(60),
+                    tolerance: gst::ClockTime::from_mseconds(500),
+                },
+            ]
+        );
+        assert_eq!(
+            steps.iter().map(|step| step.line).collect::<Vec<_>>(),
+            vec![2, 3, 5, 6, 7]
+        );
+        assert_eq!(steps[2].text, "seek 30s flush,key-unit   # jump");
+    }
+
+    #[test]
+    fn seek_defaults_to_flush() {
+        let steps = parse_script("seek 10").unwrap();
+        assert_eq!(
+            steps[0].action,
+            Action::Seek {
+                target: gst::ClockTime::from_seconds(10),
+                flags: SeekFlags::FLUSH,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        assert_eq!(
+            parse_script("play 1s\njump 3s").unwrap_err(),
+            "line 2: unknown action 'jump'"
+        );
+        assert_eq!(
+            parse_script("play").unwrap_err(),
+            "line 1: 'play' needs a time"
+        );
+        assert_eq!(
+            parse_script("seek soon").unwrap_err(),
+            "line 1: invalid time 'soon'"
+        );
+        assert_eq!(
+            parse_script("seek 1s flush,fast").unwrap_err(),
+            "line 1: unknown seek flag 'fast'"
+        );
+        assert!(parse_script("rate 0").is_err());
+        assert!(parse_script("rate x").is_err());
+        assert!(parse_script("assert 10s").is_err());
+    }
+
+    #[test]
+    fn parses_seek_flags() {
+        assert_eq!(parse_seek_flags("").unwrap(), SeekFlags::empty());
+        assert_eq!(
+            parse_seek_flags("accurate,snap-before,segment").unwrap(),
+            SeekFlags::ACCURATE | SeekFlags::SNAP_BEFORE | SeekFlags::SEGMENT
+        );
+    }
+}
@@ -0,0 +1,246 @@
+#![allow(dead_code)]
+
+use gst::prelude::*;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+// Adds and removes tee branches while the pipeline is PLAYING.
+//
+// Adding is the easy part: the new elements are brought to the state of the pipeline first,
+// and only then linked to a freshly requested tee pad, so they are ready when data arrives.
+//
+// Removing needs pad blocking: data may be flowing through the tee pad at any time, so we wait
+// for it to be idle with an IDLE probe, unlink it there, and push an EOS into the branch so that
+// elements like muxers can finish their work (e.g. write the file headers). Once the EOS reached
+// the end of the branch, the elements are shut down and removed and the tee pad released.
+// The other branches never notice.
+//
+// A branch that failed can't be drained, it is cut off the tee right away instead (isolate) and
+// then dropped. This is best effort: the tee pad must become idle first, so a buffer that was
+// already on its way into the branch may still bring its error back to the tee.
+
+// How long drop_branch waits for the tee pad of the branch to become idle
+pub const DROP_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+pub struct Branch {
+    pub name: String,
+    pub tee_pad: gst::Pad,
+    /// The elements of the branch, from the one linked to the tee to the sink
+    pub elements: Vec<gst::Element>,
+    /// Set once the branch was cut off the tee by isolate()
+    unlinked: Arc<(Mutex<bool>, Condvar)>,
+}
+
+#[derive(Clone)]
+pub struct BranchManager {
+    pipeline: gst::Pipeline,
+    tee: gst::Element,
+    branches: Arc<Mutex<HashMap<String, Branch>>>,
+}
+
+impl BranchManager {
+    pub fn new(pipeline: &gst::Pipeline, tee: &gst::Element) -> BranchManager {
+        BranchManager {
+            pipeline: pipeline.clone(),
+            tee: tee.clone(),
+            branches: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Makes the manager aware of a branch that was built and linked by hand
+    pub fn register_branch(&self, name: &str, tee_pad: &gst::Pad, elements: &[&gst::Element]) {
+        self.branches.lock().unwrap().insert(
+            name.to_string(),
+            Branch {
+                name: name.to_string(),
+                tee_pad: tee_pad.clone(),
+                elements: elements.iter().map(|element| (*element).clone()).collect(),
+                unlinked: Arc::new((Mutex::new(false), Condvar::new())),
+            },
+        );
+    }
+
+    pub fn branch_names(&self) -> Vec<String> {
+        self.branches.lock().unwrap().keys().cloned().collect()
+    }
+
+    pub fn branch(&self, name: &str) -> Option<Branch> {
+        self.branches.lock().unwrap().get(name).cloned()
+    }
+
+    // The branch an object belongs to, e.g. the source of an error message,
+    // which may be an element inside a bin of the branch like autovideosink
+    pub fn branch_of(&self, object: &gst::Object) -> Option<String> {
+        let branches = self.branches.lock().unwrap();
+        let mut current = Some(object.clone());
+        while let Some(object) = current {
+            if let Some(element) = object.downcast_ref::<gst::Element>() {
+                let branch = branches
+                    .values()
+                    .find(|branch| branch.elements.contains(element));
+                if let Some(branch) = branch {
+                    return Some(branch.name.clone());
+                }
+            }
+            current = object.parent();
+        }
+        None
+    }
+
+    // Keeps the branches up to date when an element was swapped for another one
+    pub fn replace_element(&self, old: &gst::Element, new: &gst::Element) {
+        for branch in self.branches.lock().unwrap().values_mut() {
+            for element in branch.elements.iter_mut() {
+                if element == old {
+                    *element = new.clone();
+                }
+            }
+        }
+    }
+
+    // Adds the elements to the pipeline, links them one after the other and the first one to the tee
+    pub fn add_branch(&self, name: &str, elements: &[&gst::Element]) -> Result<(), String> {
+        if self.branch(name).is_some() {
+            return Err(format!("there is already a branch named {}", name));
+        }
+        let first = elements.first().ok_or("a branch needs elements")?;
+
+        self.pipeline
+            .add_many(elements)
+            .map_err(|_| format!("failed to add the elements of branch {}", name))?;
+        gst::Element::link_many(elements)
+            .map_err(|_| format!("failed to link the elements of branch {}", name))?;
+
+        // Downstream first, so nothing receives data before it is ready for it
+        for element in elements.iter().rev() {
+            element
+                .sync_state_with_parent()
+                .map_err(|_| format!("failed to start branch {}", name))?;
+        }
+
+        let tee_pad = self
+            .tee
+            .request_pad_simple("src_%u")
+            .ok_or("failed to request a tee pad")?;
+        let sink_pad = first.static_pad("sink").ok_or("no sink pad to link")?;
+        tee_pad
+            .link(&sink_pad)
+            .map_err(|err| format!("failed to link branch {} to the tee: {:?}", name, err))?;
+
+        println!("Added branch {} on tee pad {}", name, tee_pad.name());
+        self.register_branch(name, &tee_pad, elements);
+        Ok(())
+    }
+
+    // Unlinks the branch once its tee pad is idle, drains it with an EOS and then removes it.
+    // This returns right away, the branch is gone once the EOS reached its last element.
+    pub fn remove_branch(&self, name: &str) -> Result<(), String> {
+        let branch = self
+            .branches
+            .lock()
+            .unwrap()
+            .remove(name)
+            .ok_or_else(|| format!("no branch named {}", name))?;
+
+        let first_sink_pad = branch.elements[0]
+            .static_pad("sink")
+            .ok_or("no sink pad on the first element")?;
+        let last = branch.elements.last().unwrap().clone();
+        let last_sink_pad = last.static_pad("sink").ok_or("no sink pad on the sink")?;
+
+        // When the EOS reaches the sink, the branch is drained and can go away
+        let manager = self.clone();
+        let branch_clone = branch.clone();
+        last_sink_pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+            match info.data {
+                Some(gst::PadProbeData::Event(ref event))
+                    if event.type_() == gst::EventType::Eos =>
+                {
+                    // Elements can't change their state from their own streaming thread
+                    let manager = manager.clone();
+                    let branch = branch_clone.clone();
+                    last.call_async(move |_| manager.dispose_branch(&branch));
+                    gst::PadProbeReturn::Remove
+                }
+                _ => gst::PadProbeReturn::Ok,
+            }
+        });
+
+        branch
+            .tee_pad
+            .add_probe(gst::PadProbeType::IDLE, move |tee_pad, _| {
+                let _ = tee_pad.unlink(&first_sink_pad);
+                first_sink_pad.send_event(gst::event::Eos::new());
+                gst::PadProbeReturn::Remove
+            });
+
+        Ok(())
+    }
+
+    // Cuts the branch off the tee as soon as its pad is idle, so nothing flows into it anymore.
+    // Can be called from any thread, e.g. a bus sync handler, which runs while the failing element
+    // is posting its error. That usually cuts the branch off before the error is returned upstream,
+    // but the idle probe can't run while that very buffer is still being pushed, so it isn't sure.
+    pub fn isolate(&self, name: &str) {
+        let branch = match self.branch(name) {
+            Some(branch) => branch,
+            None => return,
+        };
+        let first_sink_pad = match branch.elements[0].static_pad("sink") {
+            Some(pad) => pad,
+            None => return,
+        };
+
+        let unlinked = branch.unlinked.clone();
+        branch
+            .tee_pad
+            .add_probe(gst::PadProbeType::IDLE, move |tee_pad, _| {
+                let _ = tee_pad.unlink(&first_sink_pad);
+                let (lock, cvar) = &*unlinked;
+                *lock.lock().unwrap() = true;
+                cvar.notify_all();
+                gst::PadProbeReturn::Remove
+            });
+    }
+
+    // Removes a failed branch without draining it: isolates it, waits until it is cut off and
+    // shuts it down. If its tee pad doesn't become idle within DROP_TIMEOUT (e.g. the streaming
+    // thread is stuck), the branch is left in place and an error returned.
+    // Not to be called from a streaming thread, it would wait for itself.
+    pub fn drop_branch(&self, name: &str) -> Result<(), String> {
+        let branch = self
+            .branch(name)
+            .ok_or_else(|| format!("no branch named {}", name))?;
+        self.isolate(name);
+
+        let (lock, cvar) = &*branch.unlinked;
+        let (unlinked, _) = cvar
+            .wait_timeout_while(lock.lock().unwrap(), DROP_TIMEOUT, |unlinked| !*unlinked)
+            .unwrap();
+        if !*unlinked {
+            return Err(format!(
+                "the tee pad of branch {} didn't become idle within {:?}",
+                name, DROP_TIMEOUT
+            ));
+        }
+        drop(unlinked);
+
+        self.branches.lock().unwrap().remove(name);
+        self.dispose_branch(&branch);
+        Ok(())
+    }
+
+    fn dispose_branch(&self, branch: &Branch) {
+        for element in &branch.elements {
+            let _ = element.set_state(gst::State::Null);
+        }
+        let elements: Vec<_> = branch.elements.iter().collect();
+        let _ = self.pipeline.remove_many(&elements);
+        self.tee.release_request_pad(&branch.tee_pad);
+
+        println!("Removed branch {}", branch.name);
+    }
+}
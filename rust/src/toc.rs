@@ -0,0 +1,126 @@
+#![allow(dead_code)]
+
+use gst::prelude::*;
+
+// Containers like Matroska and MP4 can carry a table of contents (TOC): a tree of editions
+// holding chapters (which can hold sub-chapters), each with start/stop times and tags like a title.
+// Demuxers post it on the bus in a TOC message once they have parsed it.
+
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    pub title: Option<String>,
+    pub start: gst::ClockTime,
+    pub stop: Option<gst::ClockTime>,
+    /// How deep in the TOC tree the chapter is (0 for top level chapters)
+    pub depth: usize,
+}
+
+// All chapters of a TOC, flattened and sorted by start time, for navigation
+#[derive(Debug, Clone, Default)]
+pub struct ChapterList {
+    chapters: Vec<Chapter>,
+}
+
+fn entry_times(entry: &gst::TocEntry) -> (Option<gst::ClockTime>, Option<gst::ClockTime>) {
+    let to_clock_time = |time: i64| {
+        if time < 0 {
+            None
+        } else {
+            Some(gst::ClockTime::from_nseconds(time as u64))
+        }
+    };
+
+    match entry.start_stop_times() {
+        Some((start, stop)) => (to_clock_time(start), to_clock_time(stop)),
+        None => (None, None),
+    }
+}
+
+fn entry_title(entry: &gst::TocEntry) -> Option<String> {
+    let tags = entry.tags()?;
+    let title = tags.get::<gst::tags::Title>()?;
+    Some(title.get().to_string())
+}
+
+impl ChapterList {
+    pub fn from_toc(toc: &gst::Toc) -> ChapterList {
+        let mut chapters = Vec::new();
+        for entry in toc.entries() {
+            collect_chapters(&entry, 0, &mut chapters);
+        }
+        chapters.sort_by_key(|chapter| chapter.start);
+
+        ChapterList { chapters }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chapters.is_empty()
+    }
+
+    pub fn chapters(&self) -> &[Chapter] {
+        &self.chapters
+    }
+
+    // The first chapter starting after the position
+    pub fn next(&self, position: gst::ClockTime) -> Option<&Chapter> {
+        self.chapters
+            .iter()
+            .find(|chapter| chapter.start > position)
+    }
+
+    // The chapter starting before the one we are in.
+    // Like most players, we go back to the start of the current chapter first if we are a bit into it.
+    pub fn previous(&self, position: gst::ClockTime) -> Option<&Chapter> {
+        let grace = 2 * gst::ClockTime::SECOND;
+        self.chapters
+            .iter()
+            .rev()
+            .find(|chapter| chapter.start + grace < position)
+    }
+}
+
+fn collect_chapters(entry: &gst::TocEntry, depth: usize, chapters: &mut Vec<Chapter>) {
+    let mut depth = depth;
+
+    // Editions only group chapters, they are not places to go to
+    if entry.entry_type() == gst::TocEntryType::Chapter {
+        if let (Some(start), stop) = entry_times(entry) {
+            chapters.push(Chapter {
+                title: entry_title(entry),
+                start,
+                stop,
+                depth,
+            });
+        }
+        depth += 1;
+    }
+
+    for sub_entry in entry.sub_entries() {
+        collect_chapters(&sub_entry, depth, chapters);
+    }
+}
+
+pub fn print_toc(toc: &gst::Toc) {
+    println!("===== table of contents ({:?}) =====\r", toc.scope());
+    for entry in toc.entries() {
+        print_entry(&entry, 1);
+    }
+}
+
+fn print_entry(entry: &gst::TocEntry, depth: usize) {
+    let (start, stop) = entry_times(entry);
+    println!(
+        "{:indent$}{:?} '{}' {} - {}: {}\r",
+        "",
+        entry.entry_type(),
+        entry.uid(),
+        start.display(),
+        stop.display(),
+        entry_title(entry).unwrap_or_else(|| "(no title)".to_string()),
+        indent = 2 * depth
+    );
+
+    for sub_entry in entry.sub_entries() {
+        print_entry(&sub_entry, depth + 1);
+    }
+}
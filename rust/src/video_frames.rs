@@ -0,0 +1,301 @@
+#![allow(dead_code)]
+
+use crate::frame_seek;
+
+// Video frames rendered in Rust, for an appsrc.
+// Each frame is drawn as RGB into a canvas: a gradient scrolling a bit every frame, a box
+// bouncing off the edges and the frame number in the top left corner. The canvas is then
+// written into the buffer in the format of the VideoInfo: RGBA as is, or I420 converted to
+// YUV (BT.601) with one chroma sample for every 2x2 pixels.
+//
+// The format is given with "--video-format <RGBA or I420>", "--size <width>x<height>" and
+// "--framerate <frames>[/<seconds>]".
+
+const BOX_SIZE: usize = 48;
+// Pixels of one dot of the digits of the counter
+const DIGIT_SCALE: usize = 4;
+
+// 3x5 bitmaps of the digits, one row per 3 bits
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+#[derive(Debug, Clone)]
+pub struct VideoConfig {
+    pub format: gst_video::VideoFormat,
+    pub width: u32,
+    pub height: u32,
+    pub framerate: gst::Fraction,
+}
+
+impl Default for VideoConfig {
+    fn default() -> VideoConfig {
+        VideoConfig {
+            format: gst_video::VideoFormat::Rgba,
+            width: 640,
+            height: 480,
+            framerate: gst::Fraction::new(30, 1),
+        }
+    }
+}
+
+impl VideoConfig {
+    pub fn from_args(args: &[String]) -> Result<VideoConfig, String> {
+        let mut config = VideoConfig::default();
+
+        let value = |name: &str| -> Result<Option<&String>, String> {
+            match args.iter().position(|arg| arg == name) {
+                Some(i) => args
+                    .get(i + 1)
+                    .map(Some)
+                    .ok_or_else(|| format!("{} needs a value", name)),
+                None => Ok(None),
+            }
+        };
+        let invalid = |name: &str, value: &str| format!("invalid value for {}: {}", name, value);
+
+        if let Some(format) = value("--video-format")? {
+            config.format = match format.as_str() {
+                "RGBA" => gst_video::VideoFormat::Rgba,
+                "I420" => gst_video::VideoFormat::I420,
+                _ => return Err(invalid("--video-format", format)),
+            };
+        }
+        if let Some(size) = value("--size")? {
+            let (width, height) = size
+                .split_once('x')
+                .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+                .filter(|&(width, height)| width > 0 && height > 0)
+                .ok_or_else(|| invalid("--size", size))?;
+            config.width = width;
+            config.height = height;
+        }
+        if let Some(framerate) = value("--framerate")? {
+            let (numer, denom) = match framerate.split_once('/') {
+                Some((numer, denom)) => (numer.parse().ok(), denom.parse().ok()),
+                None => (framerate.parse().ok(), Some(1)),
+            };
+            config.framerate = match (numer, denom) {
+                (Some(numer), Some(denom)) if numer > 0 && denom > 0 => {
+                    gst::Fraction::new(numer, denom)
+                }
+                _ => return Err(invalid("--framerate", framerate)),
+            };
+        }
+
+        Ok(config)
+    }
+
+    pub fn info(&self) -> Result<gst_video::VideoInfo, glib::BoolError> {
+        gst_video::VideoInfo::builder(self.format, self.width, self.height)
+            .fps(self.framerate)
+            .build()
+    }
+}
+
+#[derive(Debug)]
+pub struct FrameGenerator {
+    info: gst_video::VideoInfo,
+    frame: u64,
+    canvas: Vec<[u8; 3]>,
+    /// Top left corner of the box and its speed, in pixels per frame
+    box_position: (i64, i64),
+    box_speed: (i64, i64),
+}
+
+impl FrameGenerator {
+    pub fn new(info: &gst_video::VideoInfo) -> FrameGenerator {
+        FrameGenerator {
+            info: info.clone(),
+            frame: 0,
+            canvas: vec![[0; 3]; (info.width() * info.height()) as usize],
+            box_position: (0, 0),
+            box_speed: (5, 3),
+        }
+    }
+
+    pub fn info(&self) -> &gst_video::VideoInfo {
+        &self.info
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frame
+    }
+
+    // Renders the next frame into a buffer with its timestamp and duration
+    pub fn next_buffer(&mut self) -> gst::Buffer {
+        self.render();
+
+        let mut buffer = gst::Buffer::with_size(self.info.size()).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            let framerate = self.info.fps();
+            let pts = frame_seek::frame_to_time(self.frame, framerate).expect("frame out of range");
+            let next =
+                frame_seek::frame_to_time(self.frame + 1, framerate).expect("frame out of range");
+            buffer.set_pts(pts);
+            buffer.set_duration(next - pts);
+            buffer.set_offset(self.frame);
+
+            let mut frame = gst_video::VideoFrameRef::from_buffer_ref_writable(buffer, &self.info)
+                .expect("Failed to map the frame");
+            self.write_frame(&mut frame);
+        }
+
+        self.frame += 1;
+        buffer
+    }
+
+    fn render(&mut self) {
+        let width = self.info.width() as usize;
+        let height = self.info.height() as usize;
+        let shift = self.frame as usize * 2;
+
+        // A diagonal gradient scrolling to the left
+        for y in 0..height {
+            for x in 0..width {
+                self.canvas[y * width + x] = [
+                    ((x + shift) * 255 / width.max(1)) as u8,
+                    (y * 255 / height.max(1)) as u8,
+                    (((x + y + shift) / 2) % 256) as u8,
+                ];
+            }
+        }
+
+        // The box bounces off the edges
+        let size = BOX_SIZE.min(width).min(height) as i64;
+        let (mut x, mut y) = self.box_position;
+        let (mut speed_x, mut speed_y) = self.box_speed;
+        if x + speed_x < 0 || x + speed_x + size > width as i64 {
+            speed_x = -speed_x;
+        }
+        if y + speed_y < 0 || y + speed_y + size > height as i64 {
+            speed_y = -speed_y;
+        }
+        x = (x + speed_x).clamp(0, width as i64 - size);
+        y = (y + speed_y).clamp(0, height as i64 - size);
+        self.box_position = (x, y);
+        self.box_speed = (speed_x, speed_y);
+        self.fill_rect(
+            x as usize,
+            y as usize,
+            size as usize,
+            size as usize,
+            [255; 3],
+        );
+
+        // The frame number, on a black background
+        let digits = self.frame.to_string();
+        let digit_width = 4 * DIGIT_SCALE;
+        self.fill_rect(
+            0,
+            0,
+            digits.len() * digit_width + DIGIT_SCALE,
+            7 * DIGIT_SCALE,
+            [0; 3],
+        );
+        for (i, digit) in digits.bytes().enumerate() {
+            let bitmap = DIGITS[(digit - b'0') as usize];
+            for (row, bits) in bitmap.iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) != 0 {
+                        self.fill_rect(
+                            DIGIT_SCALE + i * digit_width + column * DIGIT_SCALE,
+                            DIGIT_SCALE + row * DIGIT_SCALE,
+                            DIGIT_SCALE,
+                            DIGIT_SCALE,
+                            [255, 255, 0],
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // Clipped to the canvas
+    fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: [u8; 3]) {
+        let canvas_width = self.info.width() as usize;
+        let canvas_height = self.info.height() as usize;
+        for row in y..(y + height).min(canvas_height) {
+            for column in x..(x + width).min(canvas_width) {
+                self.canvas[row * canvas_width + column] = color;
+            }
+        }
+    }
+
+    fn write_frame(&self, frame: &mut gst_video::VideoFrameRef<&mut gst::BufferRef>) {
+        let width = self.info.width() as usize;
+        let height = self.info.height() as usize;
+        let strides: Vec<usize> = frame.plane_stride().iter().map(|s| *s as usize).collect();
+
+        match self.info.format() {
+            gst_video::VideoFormat::Rgba => {
+                let data = frame.plane_data_mut(0).unwrap();
+                for y in 0..height {
+                    let row = &mut data[y * strides[0]..];
+                    for x in 0..width {
+                        let [r, g, b] = self.canvas[y * width + x];
+                        row[x * 4..x * 4 + 4].copy_from_slice(&[r, g, b, 255]);
+                    }
+                }
+            }
+            gst_video::VideoFormat::I420 => {
+                {
+                    let luma = frame.plane_data_mut(0).unwrap();
+                    for y in 0..height {
+                        for x in 0..width {
+                            luma[y * strides[0] + x] = rgb_to_yuv(self.canvas[y * width + x]).0;
+                        }
+                    }
+                }
+
+                // Chroma of the average of each 2x2 block
+                for plane in [1, 2] {
+                    let chroma = frame.plane_data_mut(plane).unwrap();
+                    for y in 0..(height + 1) / 2 {
+                        for x in 0..(width + 1) / 2 {
+                            let mut sum = [0u32; 3];
+                            let mut count = 0;
+                            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                                let (px, py) = (x * 2 + dx, y * 2 + dy);
+                                if px < width && py < height {
+                                    let pixel = self.canvas[py * width + px];
+                                    for (sum, value) in sum.iter_mut().zip(pixel) {
+                                        *sum += u32::from(value);
+                                    }
+                                    count += 1;
+                                }
+                            }
+                            let average = [
+                                (sum[0] / count) as u8,
+                                (sum[1] / count) as u8,
+                                (sum[2] / count) as u8,
+                            ];
+                            let (_, u, v) = rgb_to_yuv(average);
+                            chroma[y * strides[plane] + x] = if plane == 1 { u } else { v };
+                        }
+                    }
+                }
+            }
+            format => unreachable!("unsupported format {:?}", format),
+        }
+    }
+}
+
+// BT.601, limited range like most video
+pub fn rgb_to_yuv([r, g, b]: [u8; 3]) -> (u8, u8, u8) {
+    let (r, g, b) = (f64::from(r), f64::from(g), f64::from(b));
+    let y = 16.0 + 0.257 * r + 0.504 * g + 0.098 * b;
+    let u = 128.0 - 0.148 * r - 0.291 * g + 0.439 * b;
+    let v = 128.0 + 0.439 * r - 0.368 * g - 0.071 * b;
+    (y.round() as u8, u.round() as u8, v.round() as u8)
+}
@@ -0,0 +1,197 @@
+#![allow(dead_code)]
+
+use gst::prelude::*;
+
+use std::sync::mpsc;
+use std::time::Duration;
+
+// Audio visualizers turn audio into video. They all have one audio sink pad and one video src pad,
+// so any of them fits between the audio queue and the videoconvert of a branch.
+// One is chosen with "--visual <name>[:<property>=<value>,...]", e.g. "--visual wavescope:style=dots",
+// and can be swapped for another one while PLAYING: the pad feeding it is blocked, so no data
+// goes through while the old element is taken out and the new one put in its place.
+
+pub const VISUALIZERS: &[&str] = &[
+    "wavescope",
+    "spectrascope",
+    "synaescope",
+    "spacescope",
+    "goom",
+    "monoscope",
+];
+
+// How long swap waits for data to block on while PLAYING
+pub const SWAP_TIMEOUT: Duration = Duration::from_secs(2);
+
+// The visualizers installed on this system
+pub fn available() -> Vec<&'static str> {
+    VISUALIZERS
+        .iter()
+        .copied()
+        .filter(|name| gst::ElementFactory::find(name).is_some())
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct VisualizerConfig {
+    pub factory: String,
+    /// Properties set from their string form, e.g. ("style", "lines")
+    pub properties: Vec<(String, String)>,
+}
+
+impl Default for VisualizerConfig {
+    // What the tutorial always used
+    fn default() -> VisualizerConfig {
+        VisualizerConfig {
+            factory: "wavescope".to_string(),
+            properties: vec![
+                ("shader".to_string(), "none".to_string()),
+                ("style".to_string(), "lines".to_string()),
+            ],
+        }
+    }
+}
+
+impl VisualizerConfig {
+    pub fn parse(text: &str) -> Result<VisualizerConfig, String> {
+        let (factory, properties) = match text.split_once(':') {
+            Some((factory, properties)) => (factory, properties),
+            None => (text, ""),
+        };
+        if !VISUALIZERS.contains(&factory) {
+            return Err(format!(
+                "unknown visualizer {}, choose one of {}",
+                factory,
+                VISUALIZERS.join(", ")
+            ));
+        }
+
+        let properties = properties
+            .split(',')
+            .filter(|property| !property.is_empty())
+            .map(|property| {
+                property
+                    .split_once('=')
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .ok_or_else(|| format!("invalid property '{}'", property))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(VisualizerConfig {
+            factory: factory.to_string(),
+            properties,
+        })
+    }
+
+    // "--visual <config>" if given, the tutorial's wavescope otherwise
+    pub fn from_args(args: &[String]) -> Result<VisualizerConfig, String> {
+        match args.iter().position(|arg| arg == "--visual") {
+            Some(i) => {
+                let config = args.get(i + 1).ok_or("--visual needs a visualizer")?;
+                VisualizerConfig::parse(config)
+            }
+            None => Ok(VisualizerConfig::default()),
+        }
+    }
+
+    pub fn make(&self, name: &str) -> Result<gst::Element, String> {
+        let visual = gst::ElementFactory::make(&self.factory, Some(name))
+            .map_err(|_| format!("{} is not available", self.factory))?;
+
+        for (property, value) in &self.properties {
+            if visual.find_property(property).is_none() {
+                return Err(format!("{} has no property {}", self.factory, property));
+            }
+            visual.set_property_from_str(property, value);
+        }
+
+        Ok(visual)
+    }
+}
+
+// Replaces the visualizer by a new one made from the config, returns the new element.
+// While PLAYING, the element feeding the visualizer must be a different thread (a queue), which
+// gets blocked while we swap them. If no data reaches the visualizer within SWAP_TIMEOUT, the swap
+// is given up. In the other states nothing is streaming, so the elements are swapped right away.
+pub fn swap(
+    pipeline: &gst::Pipeline,
+    current: &gst::Element,
+    config: &VisualizerConfig,
+) -> Result<gst::Element, String> {
+    let sink_pad = current.static_pad("sink").ok_or("no sink pad")?;
+    let src_pad = current.static_pad("src").ok_or("no src pad")?;
+    let upstream_pad = sink_pad.peer().ok_or("the visualizer is not linked")?;
+    let downstream_pad = src_pad.peer().ok_or("the visualizer is not linked")?;
+
+    // Same name, so it can still be found with by_name once the old one is gone
+    let new = config.make(&current.name())?;
+
+    if pipeline.current_state() != gst::State::Playing {
+        replace(pipeline, current, &new, &upstream_pad, &downstream_pad)?;
+        return Ok(new);
+    }
+
+    let (sender, receiver) = mpsc::sync_channel(1);
+    let pipeline = pipeline.clone();
+    let old = current.clone();
+    let new_clone = new.clone();
+    let probe_downstream_pad = downstream_pad.clone();
+    let probe = upstream_pad
+        .add_probe(gst::PadProbeType::BLOCK_DOWNSTREAM, move |pad, _| {
+            // While we are here nothing flows through the visualizer
+            let result = replace(&pipeline, &old, &new_clone, pad, &probe_downstream_pad);
+            let _ = sender.send(result);
+            gst::PadProbeReturn::Remove
+        })
+        .ok_or("failed to block the pad")?;
+
+    let result = match receiver.recv_timeout(SWAP_TIMEOUT) {
+        Ok(result) => result,
+        Err(_) => {
+            upstream_pad.remove_probe(probe);
+            // The probe may have run right before we removed it
+            receiver.try_recv().unwrap_or_else(|_| {
+                Err(format!(
+                    "no data reached the visualizer within {:?}",
+                    SWAP_TIMEOUT
+                ))
+            })
+        }
+    };
+    result?;
+    Ok(new)
+}
+
+// Takes the old visualizer out from between the two pads and puts the new one in its place
+fn replace(
+    pipeline: &gst::Pipeline,
+    old: &gst::Element,
+    new: &gst::Element,
+    upstream_pad: &gst::Pad,
+    downstream_pad: &gst::Pad,
+) -> Result<(), String> {
+    let sink_pad = old.static_pad("sink").ok_or("no sink pad")?;
+    let src_pad = old.static_pad("src").ok_or("no src pad")?;
+    upstream_pad
+        .unlink(&sink_pad)
+        .map_err(|_| "failed to unlink")?;
+    src_pad
+        .unlink(downstream_pad)
+        .map_err(|_| "failed to unlink")?;
+    old.set_state(gst::State::Null)
+        .map_err(|_| "failed to stop the old visualizer")?;
+    pipeline.remove(old).map_err(|_| "failed to remove")?;
+
+    pipeline.add(new).map_err(|_| "failed to add")?;
+    let new_sink = new.static_pad("sink").ok_or("no sink pad")?;
+    let new_src = new.static_pad("src").ok_or("no src pad")?;
+    new_src
+        .link(downstream_pad)
+        .map_err(|err| format!("failed to link: {:?}", err))?;
+    upstream_pad
+        .link(&new_sink)
+        .map_err(|err| format!("failed to link: {:?}", err))?;
+    new.sync_state_with_parent()
+        .map_err(|_| "failed to start the new visualizer")?;
+    Ok(())
+}
@@ -0,0 +1,621 @@
+#![allow(dead_code)]
+
+use glib::translate::IntoGlib;
+use gst_audio::AudioChannelPosition as Position;
+use gst_audio::AudioFormat;
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// Writes raw audio into a WAV file, without any GStreamer element involved, so what an appsink
+// receives can be kept byte for byte (e.g. as the golden output of a test) and opened anywhere.
+//
+// The sizes in the header are only known at the end: they are written as 0 first and patched
+// by finish(), which must be called once the stream is over (on EOS). They are also patched
+// after every second of samples, so a file cut short (Ctrl-C) still tells most of its size.
+// Anything Write + Seek can be written to, a &mut Cursor<Vec<u8>> keeps the whole file in memory.
+//
+// WAV samples are little endian: unsigned 8 bits, or signed 16, 24 and 32 bits, or floats.
+// Big endian samples are swapped and S24_32 is packed into 3 bytes, other formats are refused.
+// More than 2 channels or more than 16 bits use WAVE_FORMAT_EXTENSIBLE, which also tells the
+// speaker position of each channel. The channels of a WAV file come in the order of the bits of
+// its channel mask, which isn't GStreamer's order for the top speakers, so the channels of each
+// frame get reordered on the way in and out.
+//
+// read_header() goes the other way and finds the format and the samples of an existing file.
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+// The GUID of KSDATAFORMAT_SUBTYPE_PCM/IEEE_FLOAT after its first two bytes (the format tag)
+const SUBFORMAT_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+// How the samples of the stream end up in the file
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Conversion {
+    Copy,
+    // Reverse the bytes of each sample
+    Swap,
+    // Keep the 3 low bytes of each 4 bytes sample, from little or big endian
+    Pack24 { big_endian: bool },
+}
+
+#[derive(Debug)]
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    conversion: Conversion,
+    /// Bytes of one sample in the stream
+    sample_size: usize,
+    /// Bytes of one sample in the file
+    file_sample_size: usize,
+    /// The stream channel of each file channel, if they aren't in the same order
+    channel_order: Option<Vec<usize>>,
+    /// Offset of the size of the data chunk in the header
+    data_size_offset: u64,
+    data_size: u64,
+    /// The data size the header tells so far
+    written_size: u64,
+    /// Bytes of one second of samples in the file
+    byte_rate: u64,
+    finished: bool,
+}
+
+// The speaker of each bit of the WAV channel mask, the channels of the file come in this order
+const SPEAKERS: [Position; 18] = [
+    Position::FrontLeft,
+    Position::FrontRight,
+    Position::FrontCenter,
+    Position::Lfe1,
+    Position::RearLeft,
+    Position::RearRight,
+    Position::FrontLeftOfCenter,
+    Position::FrontRightOfCenter,
+    Position::RearCenter,
+    Position::SideLeft,
+    Position::SideRight,
+    Position::TopCenter,
+    Position::TopFrontLeft,
+    Position::TopFrontCenter,
+    Position::TopFrontRight,
+    Position::TopRearLeft,
+    Position::TopRearCenter,
+    Position::TopRearRight,
+];
+
+// The bit of the WAV channel mask for each channel, if they all have one
+fn speaker_bits(info: &gst_audio::AudioInfo) -> Option<Vec<usize>> {
+    info.positions()?
+        .iter()
+        .map(|position| {
+            // A mono channel is played on the front center speaker
+            let position = if *position == Position::Mono {
+                Position::FrontCenter
+            } else {
+                *position
+            };
+            SPEAKERS.iter().position(|speaker| *speaker == position)
+        })
+        .collect()
+}
+
+fn channel_mask(info: &gst_audio::AudioInfo) -> u32 {
+    speaker_bits(info)
+        .unwrap_or_default()
+        .iter()
+        .fold(0, |mask, bit| mask | (1 << bit))
+}
+
+// The channels sorted by key, None if they already are
+fn sorted_channels<K: Ord>(keys: &[K]) -> Option<Vec<usize>> {
+    let mut order: Vec<usize> = (0..keys.len()).collect();
+    order.sort_by_key(|&channel| &keys[channel]);
+    if order.iter().enumerate().all(|(i, &channel)| i == channel) {
+        None
+    } else {
+        Some(order)
+    }
+}
+
+// Moves the channels of every frame: channel i of the result is channel order[i] of the data
+pub fn reorder_channels(data: &[u8], sample_size: usize, order: &[usize]) -> Vec<u8> {
+    let mut reordered = Vec::with_capacity(data.len());
+    for frame in data.chunks_exact(sample_size * order.len()) {
+        for &channel in order {
+            reordered.extend(&frame[channel * sample_size..(channel + 1) * sample_size]);
+        }
+    }
+    reordered
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        info: &gst_audio::AudioInfo,
+    ) -> io::Result<WavWriter<BufWriter<File>>> {
+        WavWriter::new(BufWriter::new(File::create(path)?), info)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    // Writes the header, the samples follow with write()
+    pub fn new(mut writer: W, info: &gst_audio::AudioInfo) -> io::Result<WavWriter<W>> {
+        let unsupported = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("WAV can't hold {} samples", info.format().to_str()),
+            )
+        };
+
+        let (conversion, bits, float) = match info.format() {
+            AudioFormat::U8 => (Conversion::Copy, 8, false),
+            AudioFormat::S16le => (Conversion::Copy, 16, false),
+            AudioFormat::S16be => (Conversion::Swap, 16, false),
+            AudioFormat::S24le => (Conversion::Copy, 24, false),
+            AudioFormat::S24be => (Conversion::Swap, 24, false),
+            AudioFormat::S2432le => (Conversion::Pack24 { big_endian: false }, 24, false),
+            AudioFormat::S2432be => (Conversion::Pack24 { big_endian: true }, 24, false),
+            AudioFormat::S32le => (Conversion::Copy, 32, false),
+            AudioFormat::S32be => (Conversion::Swap, 32, false),
+            AudioFormat::F32le => (Conversion::Copy, 32, true),
+            AudioFormat::F32be => (Conversion::Swap, 32, true),
+            AudioFormat::F64le => (Conversion::Copy, 64, true),
+            AudioFormat::F64be => (Conversion::Swap, 64, true),
+            _ => return Err(unsupported()),
+        };
+        if info.layout() != gst_audio::AudioLayout::Interleaved {
+            return Err(unsupported());
+        }
+
+        let channels = info.channels() as u16;
+        let block_align = channels * bits / 8;
+        let byte_rate = info.rate() * u32::from(block_align);
+        let format_tag = if float {
+            WAVE_FORMAT_IEEE_FLOAT
+        } else {
+            WAVE_FORMAT_PCM
+        };
+        let extensible = channels > 2 || bits > 16;
+
+        writer.write_all(b"RIFF")?;
+        // RIFF size, patched by finish()
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&(if extensible { 40u32 } else { 16u32 }).to_le_bytes())?;
+        let tag = if extensible {
+            WAVE_FORMAT_EXTENSIBLE
+        } else {
+            format_tag
+        };
+        writer.write_all(&tag.to_le_bytes())?;
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&info.rate().to_le_bytes())?;
+        writer.write_all(&byte_rate.to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&bits.to_le_bytes())?;
+        if extensible {
+            // Size of the extension, valid bits per sample, channel mask and sub format
+            writer.write_all(&22u16.to_le_bytes())?;
+            writer.write_all(&bits.to_le_bytes())?;
+            writer.write_all(&channel_mask(info).to_le_bytes())?;
+            writer.write_all(&format_tag.to_le_bytes())?;
+            writer.write_all(&SUBFORMAT_GUID_TAIL)?;
+        }
+
+        writer.write_all(b"data")?;
+        let data_size_offset = writer.stream_position()?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            writer,
+            conversion,
+            sample_size: (info.format_info().width() / 8) as usize,
+            file_sample_size: usize::from(bits / 8),
+            channel_order: speaker_bits(info).and_then(|bits| sorted_channels(&bits)),
+            data_size_offset,
+            data_size: 0,
+            written_size: 0,
+            byte_rate: u64::from(byte_rate),
+            finished: false,
+        })
+    }
+
+    // Appends samples in the format given to new()
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        // Whole samples only
+        let data = &data[..data.len() - data.len() % self.sample_size];
+        let converted: Cow<[u8]> = match self.conversion {
+            Conversion::Copy => Cow::Borrowed(data),
+            Conversion::Swap => {
+                let mut swapped = Vec::with_capacity(data.len());
+                for sample in data.chunks_exact(self.sample_size) {
+                    swapped.extend(sample.iter().rev());
+                }
+                Cow::Owned(swapped)
+            }
+            Conversion::Pack24 { big_endian } => {
+                let mut packed = Vec::with_capacity(data.len() / 4 * 3);
+                for sample in data.chunks_exact(self.sample_size) {
+                    if big_endian {
+                        packed.extend([sample[3], sample[2], sample[1]]);
+                    } else {
+                        packed.extend(&sample[..3]);
+                    }
+                }
+                Cow::Owned(packed)
+            }
+        };
+        // Whole frames only from here
+        let converted = match self.channel_order {
+            Some(ref order) => {
+                Cow::Owned(reorder_channels(&converted, self.file_sample_size, order))
+            }
+            None => converted,
+        };
+        self.writer.write_all(&converted)?;
+
+        self.data_size += converted.len() as u64;
+        if self.data_size - self.written_size >= self.byte_rate {
+            self.write_sizes(0)?;
+        }
+        Ok(())
+    }
+
+    // Patches the sizes into the header. The data chunk has to be padded to an even size.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        let padding = (self.data_size % 2) as u32;
+        if padding == 1 {
+            self.writer.write_all(&[0])?;
+        }
+        self.write_sizes(padding)
+    }
+
+    // Writes the sizes for the samples so far into the header, and everything out to the file
+    fn write_sizes(&mut self, padding: u32) -> io::Result<()> {
+        // Sizes are 32 bits, longer files are cut off at 4 GB for the readers
+        let data_size = self
+            .data_size
+            .min(u64::from(u32::MAX) - self.data_size_offset) as u32;
+        // Counted in 64 bits, at the cap the padding byte would take this one past 4 GB
+        let riff_size = (u64::from(data_size) + self.data_size_offset + u64::from(padding) - 4)
+            .min(u64::from(u32::MAX)) as u32;
+        self.written_size = self.data_size;
+
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&riff_size.to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(self.data_size_offset))?;
+        self.writer.write_all(&data_size.to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()
+    }
+
+    pub fn data_size(&self) -> u64 {
+        self.data_size
+    }
+}
+
+impl<W: Write + Seek> Drop for WavWriter<W> {
+    // A file that was never finished would claim to be empty
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            eprintln!("Failed to finish the WAV file: {}", err);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WavHeader {
+    pub info: gst_audio::AudioInfo,
+    /// Where the samples start in the file
+    pub data_offset: u64,
+    /// Bytes of samples
+    pub data_size: u64,
+    /// The file channel of each channel of info, if the file has them in another order than
+    /// GStreamer (see reorder_channels)
+    pub channel_order: Option<Vec<usize>>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+// Reads the chunks up to the data one, and leaves the reader at the first sample
+pub fn read_header<R: Read + Seek>(reader: &mut R) -> io::Result<WavHeader> {
+    let mut riff = [0; 12];
+    reader.read_exact(&mut riff)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err(invalid("not a WAV file"));
+    }
+
+    let mut fmt: Option<Vec<u8>> = None;
+    loop {
+        let mut chunk = [0; 8];
+        reader.read_exact(&mut chunk)?;
+        let size = u64::from(read_u32(&chunk, 4));
+
+        match &chunk[0..4] {
+            b"fmt " => {
+                let mut bytes = vec![0; size as usize];
+                reader.read_exact(&mut bytes)?;
+                if size % 2 == 1 {
+                    reader.seek(SeekFrom::Current(1))?;
+                }
+                fmt = Some(bytes);
+            }
+            b"data" => {
+                let fmt = fmt.ok_or_else(|| invalid("no fmt chunk before the samples"))?;
+                let data_offset = reader.stream_position()?;
+                // Files written while streaming may not know their size
+                let end = reader.seek(SeekFrom::End(0))?;
+                reader.seek(SeekFrom::Start(data_offset))?;
+                let data_size = if size == 0 || size == u64::from(u32::MAX) {
+                    end - data_offset
+                } else {
+                    size.min(end - data_offset)
+                };
+
+                let (info, channel_order) = parse_fmt(&fmt)?;
+                return Ok(WavHeader {
+                    info,
+                    data_offset,
+                    data_size,
+                    channel_order,
+                });
+            }
+            // LIST, fact, cue... we don't need them
+            _ => {
+                reader.seek(SeekFrom::Current((size + size % 2) as i64))?;
+            }
+        }
+    }
+}
+
+// The format of the samples, and the order of the channels if it isn't GStreamer's
+fn parse_fmt(fmt: &[u8]) -> io::Result<(gst_audio::AudioInfo, Option<Vec<usize>>)> {
+    if fmt.len() < 16 {
+        return Err(invalid("fmt chunk too short"));
+    }
+    let mut tag = read_u16(fmt, 0);
+    let channels = u32::from(read_u16(fmt, 2));
+    let rate = read_u32(fmt, 4);
+    let bits = read_u16(fmt, 14);
+    let mut valid_bits = bits;
+    let mut mask = 0;
+
+    if tag == WAVE_FORMAT_EXTENSIBLE {
+        if fmt.len() < 40 {
+            return Err(invalid("fmt chunk too short"));
+        }
+        valid_bits = read_u16(fmt, 18);
+        mask = read_u32(fmt, 20);
+        tag = read_u16(fmt, 24);
+    }
+
+    let format = match (tag, bits, valid_bits) {
+        (WAVE_FORMAT_PCM, 8, _) => AudioFormat::U8,
+        (WAVE_FORMAT_PCM, 16, _) => AudioFormat::S16le,
+        (WAVE_FORMAT_PCM, 24, _) => AudioFormat::S24le,
+        // 24 valid bits in 32 are the high ones, which makes it plain S32
+        (WAVE_FORMAT_PCM, 32, _) => AudioFormat::S32le,
+        (WAVE_FORMAT_IEEE_FLOAT, 32, _) => AudioFormat::F32le,
+        (WAVE_FORMAT_IEEE_FLOAT, 64, _) => AudioFormat::F64le,
+        _ => {
+            return Err(invalid(&format!(
+                "unsupported WAV format {} with {} bits",
+                tag, bits
+            )))
+        }
+    };
+
+    let builder = gst_audio::AudioInfo::builder(format, rate, channels);
+    // The channels of the file, in the order of the bits
+    let positions: Vec<Position> = (0..SPEAKERS.len())
+        .filter(|bit| mask & (1 << bit) != 0)
+        .map(|bit| SPEAKERS[bit])
+        .collect();
+    let (info, channel_order) = if positions.len() == channels as usize && channels > 1 {
+        // GStreamer wants them in the order of its positions
+        let keys: Vec<i32> = positions
+            .iter()
+            .map(|position| position.into_glib())
+            .collect();
+        let channel_order = sorted_channels(&keys);
+        let positions: Vec<Position> = match channel_order {
+            Some(ref order) => order.iter().map(|&channel| positions[channel]).collect(),
+            None => positions,
+        };
+        (builder.positions(&positions).build(), channel_order)
+    } else if channels > 2 {
+        (
+            builder.flags(gst_audio::AudioFlags::UNPOSITIONED).build(),
+            None,
+        )
+    } else {
+        // Mono or stereo
+        (builder.build(), None)
+    };
+
+    let info = info.map_err(|_| invalid("invalid audio format"))?;
+    Ok((info, channel_order))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn audio_info(
+        format: AudioFormat,
+        channels: u32,
+        positions: Option<&[Position]>,
+    ) -> gst_audio::AudioInfo {
+        gst::init().unwrap();
+        let builder = gst_audio::AudioInfo::builder(format, 48000, channels);
+        match positions {
+            Some(positions) => builder.positions(positions).build().unwrap(),
+            None => builder.build().unwrap(),
+        }
+    }
+
+    // Writes a few frames into memory and reads them back
+    fn round_trip(info: &gst_audio::AudioInfo) {
+        let samples: Vec<u8> = (0..info.bpf() * 10).map(|i| i as u8).collect();
+
+        let mut cursor = Cursor::new(Vec::new());
+        {
+            let mut writer = WavWriter::new(&mut cursor, info).unwrap();
+            writer.write(&samples).unwrap();
+            writer.finish().unwrap();
+        }
+
+        cursor.set_position(0);
+        let header = read_header(&mut cursor).unwrap();
+        assert_eq!(&header.info, info);
+        assert_eq!(header.data_size, samples.len() as u64);
+
+        let mut data = Vec::new();
+        cursor.read_to_end(&mut data).unwrap();
+        if let Some(ref order) = header.channel_order {
+            let sample_size = (info.bpf() / info.channels()) as usize;
+            data = reorder_channels(&data, sample_size, order);
+        }
+        assert_eq!(data, samples);
+    }
+
+    #[test]
+    fn round_trips_s16() {
+        round_trip(&audio_info(AudioFormat::S16le, 1, None));
+        round_trip(&audio_info(AudioFormat::S16le, 2, None));
+        round_trip(&audio_info(AudioFormat::S16le, 4, None));
+    }
+
+    #[test]
+    fn round_trips_s24() {
+        round_trip(&audio_info(AudioFormat::S24le, 1, None));
+        round_trip(&audio_info(AudioFormat::S24le, 2, None));
+    }
+
+    #[test]
+    fn round_trips_f32() {
+        round_trip(&audio_info(AudioFormat::F32le, 2, None));
+        round_trip(&audio_info(
+            AudioFormat::F32le,
+            6,
+            Some(&[
+                Position::FrontLeft,
+                Position::FrontRight,
+                Position::FrontCenter,
+                Position::Lfe1,
+                Position::RearLeft,
+                Position::RearRight,
+            ]),
+        ));
+    }
+
+    #[test]
+    fn reorders_top_speakers() {
+        // GStreamer has TopFrontCenter before TopCenter, WAV the other way round
+        let info = audio_info(
+            AudioFormat::S16le,
+            4,
+            Some(&[
+                Position::FrontLeft,
+                Position::FrontRight,
+                Position::TopFrontCenter,
+                Position::TopCenter,
+            ]),
+        );
+        round_trip(&info);
+
+        let mut cursor = Cursor::new(Vec::new());
+        WavWriter::new(&mut cursor, &info)
+            .unwrap()
+            .write(&[0, 0, 1, 1, 2, 2, 3, 3])
+            .unwrap();
+        cursor.set_position(0);
+        let header = read_header(&mut cursor).unwrap();
+        assert_eq!(header.channel_order, Some(vec![0, 1, 3, 2]));
+        let mut data = Vec::new();
+        cursor.read_to_end(&mut data).unwrap();
+        assert_eq!(data, [0, 0, 1, 1, 3, 3, 2, 2]);
+    }
+
+    #[test]
+    fn reads_24_bits_in_32_as_s32() {
+        let mut fmt = Vec::new();
+        fmt.extend(WAVE_FORMAT_EXTENSIBLE.to_le_bytes());
+        fmt.extend(2u16.to_le_bytes());
+        fmt.extend(48000u32.to_le_bytes());
+        fmt.extend((48000u32 * 8).to_le_bytes());
+        fmt.extend(8u16.to_le_bytes());
+        fmt.extend(32u16.to_le_bytes());
+        fmt.extend(22u16.to_le_bytes());
+        fmt.extend(24u16.to_le_bytes());
+        fmt.extend(3u32.to_le_bytes());
+        fmt.extend(WAVE_FORMAT_PCM.to_le_bytes());
+        fmt.extend(SUBFORMAT_GUID_TAIL);
+
+        gst::init().unwrap();
+        let (info, channel_order) = parse_fmt(&fmt).unwrap();
+        assert_eq!(info.format(), AudioFormat::S32le);
+        assert_eq!(info.channels(), 2);
+        assert_eq!(channel_order, None);
+    }
+
+    #[test]
+    fn sizes_are_written_while_streaming() {
+        let info = audio_info(AudioFormat::S16le, 2, None);
+        let second = vec![0; (info.rate() * info.bpf()) as usize];
+
+        let mut cursor = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut cursor, &info).unwrap();
+        writer.write(&second).unwrap();
+        writer.write(&second[..4]).unwrap();
+
+        // Not finished yet, the header tells the first second
+        let offset = writer.data_size_offset as usize;
+        let bytes = writer.writer.get_ref();
+        assert_eq!(read_u32(bytes, offset) as usize, second.len());
+        assert_eq!(read_u32(bytes, 4) as usize, offset + second.len() - 4);
+    }
+
+    #[test]
+    fn sizes_stop_at_4_gb() {
+        let info = audio_info(AudioFormat::S16le, 2, None);
+        let mut cursor = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut cursor, &info).unwrap();
+
+        // As if 5 GB and a byte had been written, which needs a padding byte
+        writer.data_size = (5 << 30) + 1;
+        writer.write_sizes(1).unwrap();
+
+        let offset = writer.data_size_offset as usize;
+        let bytes = writer.writer.get_ref();
+        assert_eq!(read_u32(bytes, offset), u32::MAX - offset as u32);
+        assert_eq!(read_u32(bytes, 4), u32::MAX - 3);
+    }
+}
@@ -0,0 +1,583 @@
+#![allow(dead_code)]
+
+use std::f64::consts::PI;
+use std::fmt;
+
+// Waveforms to feed an appsrc with.
+// A generator produces one sample at a time as an f64 between -1.0 and 1.0, and fill() turns
+// those into the raw bytes of whatever format the AudioInfo describes (any width, signed or
+// unsigned, integer or float, either endianness), the same sample going to every channel.
+//
+// They are chosen with "--wave <name>[:<parameter>=<value>,...]", e.g. "--wave sine:freq=220",
+// "--wave chirp:from=100,to=8000,duration=5" or "--wave pink-noise:amplitude=0.3".
+
+pub const WAVEFORMS: &[&str] = &[
+    "sine",
+    "square",
+    "sawtooth",
+    "triangle",
+    "white-noise",
+    "pink-noise",
+    "chirp",
+    "psychedelic",
+];
+
+const DEFAULT_FREQ: f64 = 440.0;
+const DEFAULT_AMPLITUDE: f64 = 0.5;
+
+pub trait WaveformGenerator: Send + fmt::Debug {
+    // The next sample, between -1.0 and 1.0
+    fn next_sample(&mut self) -> f64;
+
+    // Called before each buffer, for generators that change once per buffer
+    fn start_buffer(&mut self) {}
+
+    // Fills a buffer of interleaved samples in the format of the info
+    fn fill(&mut self, info: &gst_audio::AudioInfo, data: &mut [u8]) {
+        self.start_buffer();
+
+        let format = info.format_info();
+        let sample_size = (format.width() / 8) as usize;
+        for frame in data.chunks_exact_mut(info.bpf() as usize) {
+            let sample = self.next_sample();
+            for bytes in frame.chunks_exact_mut(sample_size) {
+                write_sample(&format, sample, bytes);
+            }
+        }
+    }
+}
+
+// Writes one sample in the given format. `bytes` is exactly one sample wide.
+pub fn write_sample(format: &gst_audio::AudioFormatInfo, sample: f64, bytes: &mut [u8]) {
+    let sample = sample.clamp(-1.0, 1.0);
+
+    if format.is_float() {
+        if format.width() == 64 {
+            let value = if format.is_little_endian() {
+                sample.to_le_bytes()
+            } else {
+                sample.to_be_bytes()
+            };
+            bytes.copy_from_slice(&value);
+        } else {
+            let sample = sample as f32;
+            let value = if format.is_little_endian() {
+                sample.to_le_bytes()
+            } else {
+                sample.to_be_bytes()
+            };
+            bytes.copy_from_slice(&value);
+        }
+        return;
+    }
+
+    // Integers use `depth` bits of the `width` ones (S24_32 is 24 bits of data in 32),
+    // unsigned ones are centered around half their range
+    let max = ((1i64 << (format.depth() - 1)) - 1) as f64;
+    let mut value = (sample * max).round() as i64;
+    if !format.is_signed() {
+        value += 1i64 << (format.depth() - 1);
+    }
+
+    let value = value.to_le_bytes();
+    let width = bytes.len();
+    if format.is_little_endian() {
+        bytes.copy_from_slice(&value[..width]);
+    } else {
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = value[width - 1 - i];
+        }
+    }
+}
+
+// Reads one sample in the given format as a value between -1.0 and 1.0, the reverse of write_sample
+pub fn read_sample(format: &gst_audio::AudioFormatInfo, bytes: &[u8]) -> f64 {
+    if format.is_float() {
+        return if format.width() == 64 {
+            let mut value = [0; 8];
+            value.copy_from_slice(bytes);
+            if format.is_little_endian() {
+                f64::from_le_bytes(value)
+            } else {
+                f64::from_be_bytes(value)
+            }
+        } else {
+            let mut value = [0; 4];
+            value.copy_from_slice(bytes);
+            f64::from(if format.is_little_endian() {
+                f32::from_le_bytes(value)
+            } else {
+                f32::from_be_bytes(value)
+            })
+        };
+    }
+
+    let mut value = [0; 8];
+    let width = bytes.len();
+    if format.is_little_endian() {
+        value[..width].copy_from_slice(bytes);
+    } else {
+        for (i, byte) in bytes.iter().enumerate() {
+            value[width - 1 - i] = *byte;
+        }
+    }
+    // Only the low `depth` bits count, sign extended from there
+    let depth = format.depth();
+    let mut value = i64::from_le_bytes(value) & ((1i64 << depth) - 1);
+    if format.is_signed() {
+        value = (value << (64 - depth)) >> (64 - depth);
+    } else {
+        value -= 1i64 << (depth - 1);
+    }
+
+    value as f64 / ((1i64 << (depth - 1)) - 1) as f64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Shape {
+    Sine,
+    Square,
+    Sawtooth,
+    Triangle,
+}
+
+// Periodic waveforms, driven by a phase going from 0 to 1 over each period
+#[derive(Debug)]
+pub struct Oscillator {
+    shape: Shape,
+    freq: f64,
+    amplitude: f64,
+    rate: f64,
+    phase: f64,
+}
+
+impl WaveformGenerator for Oscillator {
+    fn next_sample(&mut self) -> f64 {
+        let value = match self.shape {
+            Shape::Sine => (2.0 * PI * self.phase).sin(),
+            Shape::Square => {
+                if self.phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Shape::Sawtooth => 2.0 * self.phase - 1.0,
+            Shape::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+        };
+
+        self.phase = (self.phase + self.freq / self.rate).fract();
+        self.amplitude * value
+    }
+}
+
+// Xorshift, plenty random enough for noise
+#[derive(Debug)]
+struct Random(u64);
+
+impl Random {
+    // Between -1.0 and 1.0
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+}
+
+#[derive(Debug)]
+pub struct WhiteNoise {
+    amplitude: f64,
+    random: Random,
+}
+
+impl WaveformGenerator for WhiteNoise {
+    fn next_sample(&mut self) -> f64 {
+        self.amplitude * self.random.next()
+    }
+}
+
+// White noise through Paul Kellet's filter: the power drops 3 dB per octave
+#[derive(Debug)]
+pub struct PinkNoise {
+    amplitude: f64,
+    random: Random,
+    b: [f64; 7],
+}
+
+impl WaveformGenerator for PinkNoise {
+    fn next_sample(&mut self) -> f64 {
+        let white = self.random.next();
+        let b = &mut self.b;
+        b[0] = 0.99886 * b[0] + white * 0.0555179;
+        b[1] = 0.99332 * b[1] + white * 0.0750759;
+        b[2] = 0.96900 * b[2] + white * 0.1538520;
+        b[3] = 0.86650 * b[3] + white * 0.3104856;
+        b[4] = 0.55000 * b[4] + white * 0.5329522;
+        b[5] = -0.7616 * b[5] - white * 0.0168980;
+        let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+        b[6] = white * 0.115926;
+
+        // The filter has a gain of about 5, its rare peaks above that are clipped
+        (self.amplitude * pink * 0.2).clamp(-1.0, 1.0)
+    }
+}
+
+// A sine sweeping exponentially from one frequency to another, then starting over
+#[derive(Debug)]
+pub struct Chirp {
+    from: f64,
+    to: f64,
+    /// Seconds of one sweep
+    duration: f64,
+    amplitude: f64,
+    rate: f64,
+    time: f64,
+    phase: f64,
+}
+
+impl WaveformGenerator for Chirp {
+    fn next_sample(&mut self) -> f64 {
+        let freq = self.from * (self.to / self.from).powf(self.time / self.duration);
+        let value = (2.0 * PI * self.phase).sin();
+
+        self.phase = (self.phase + freq / self.rate).fract();
+        self.time += 1.0 / self.rate;
+        if self.time >= self.duration {
+            self.time = 0.0;
+        }
+        self.amplitude * value
+    }
+}
+
+// The tutorial's waveform: an oscillator whose frequency is itself driven by a slower
+// oscillator, stepped once per buffer
+#[derive(Debug)]
+pub struct Psychedelic {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    freq: f64,
+}
+
+impl Default for Psychedelic {
+    fn default() -> Psychedelic {
+        Psychedelic {
+            a: 0.0,
+            b: 1.0,
+            c: 0.0,
+            d: 1.0,
+            freq: 0.0,
+        }
+    }
+}
+
+impl WaveformGenerator for Psychedelic {
+    fn start_buffer(&mut self) {
+        self.c += self.d;
+        self.d -= self.c / 1000.0;
+        self.freq = 1100.0 + 1000.0 * self.d;
+    }
+
+    fn next_sample(&mut self) -> f64 {
+        self.a += self.b;
+        self.b -= self.a / self.freq;
+        // The tutorial wrote 500 * (a as i16) into S16 samples. a slowly grows over the minutes,
+        // so the result is clipped to full scale.
+        (500.0 * self.a.trunc() / f64::from(i16::MAX)).clamp(-1.0, 1.0)
+    }
+}
+
+// Makes a generator from "<name>[:<parameter>=<value>,...]" for the given sample rate
+pub fn from_spec(spec: &str, rate: u32) -> Result<Box<dyn WaveformGenerator>, String> {
+    let (name, parameters) = match spec.split_once(':') {
+        Some((name, parameters)) => (name, parameters),
+        None => (spec, ""),
+    };
+
+    let mut values = Vec::new();
+    for parameter in parameters.split(',').filter(|p| !p.is_empty()) {
+        let (key, value) = parameter
+            .split_once('=')
+            .ok_or_else(|| format!("invalid parameter '{}'", parameter))?;
+        let value = value
+            .parse::<f64>()
+            .map_err(|_| format!("invalid value for {}: {}", key, value))?;
+        values.push((key, value));
+    }
+
+    // The parameters each waveform understands, anything else is most likely a typo
+    let keys: &[&str] = match name {
+        "sine" | "square" | "sawtooth" | "triangle" => &["freq", "amplitude"],
+        "white-noise" | "pink-noise" => &["amplitude"],
+        "chirp" => &["from", "to", "duration", "amplitude"],
+        _ => &[],
+    };
+    if WAVEFORMS.contains(&name) {
+        if let Some((key, _)) = values.iter().find(|(key, _)| !keys.contains(key)) {
+            return Err(if keys.is_empty() {
+                format!("{} takes no parameters, got {}", name, key)
+            } else {
+                format!(
+                    "unknown parameter {} for {}, choose one of {}",
+                    key,
+                    name,
+                    keys.join(", ")
+                )
+            });
+        }
+    }
+
+    let parameter = |key: &str, default: f64| {
+        values
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| *value)
+            .unwrap_or(default)
+    };
+
+    let rate = f64::from(rate);
+    let amplitude = parameter("amplitude", DEFAULT_AMPLITUDE);
+    if !(0.0..=1.0).contains(&amplitude) {
+        return Err("the amplitude must be between 0 and 1".to_string());
+    }
+    // Seeded from the clock, each run is different
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|time| time.as_nanos() as u64)
+        .unwrap_or(0)
+        | 1;
+    let oscillator = |shape| -> Box<dyn WaveformGenerator> {
+        Box::new(Oscillator {
+            shape,
+            freq: parameter("freq", DEFAULT_FREQ),
+            amplitude,
+            rate,
+            phase: 0.0,
+        })
+    };
+
+    let generator: Box<dyn WaveformGenerator> = match name {
+        "sine" => oscillator(Shape::Sine),
+        "square" => oscillator(Shape::Square),
+        "sawtooth" => oscillator(Shape::Sawtooth),
+        "triangle" => oscillator(Shape::Triangle),
+        "white-noise" => Box::new(WhiteNoise {
+            amplitude,
+            random: Random(seed),
+        }),
+        "pink-noise" => Box::new(PinkNoise {
+            amplitude,
+            random: Random(seed),
+            b: [0.0; 7],
+        }),
+        "chirp" => {
+            let from = parameter("from", 100.0);
+            let to = parameter("to", 10_000.0);
+            let duration = parameter("duration", 5.0);
+            if from <= 0.0 || to <= 0.0 || duration <= 0.0 {
+                return Err("chirp needs positive from, to and duration".to_string());
+            }
+            Box::new(Chirp {
+                from,
+                to,
+                duration,
+                amplitude,
+                rate,
+                time: 0.0,
+                phase: 0.0,
+            })
+        }
+        "psychedelic" => Box::new(Psychedelic::default()),
+        _ => {
+            return Err(format!(
+                "unknown waveform {}, choose one of {}",
+                name,
+                WAVEFORMS.join(", ")
+            ))
+        }
+    };
+
+    Ok(generator)
+}
+
+// "--wave <spec>" if given, the tutorial's psychedelic waveform otherwise
+pub fn from_args(args: &[String], rate: u32) -> Result<Box<dyn WaveformGenerator>, String> {
+    match args.iter().position(|arg| arg == "--wave") {
+        Some(i) => {
+            let spec = args.get(i + 1).ok_or("--wave needs a waveform")?;
+            from_spec(spec, rate)
+        }
+        None => from_spec("psychedelic", rate),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gst_audio::AudioFormat;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn round_trip(format: AudioFormat) {
+        gst::init().unwrap();
+        let format = gst_audio::AudioFormatInfo::from_format(format);
+        // One step of the integer formats is the most we can lose
+        let step = if format.is_float() {
+            1e-6
+        } else {
+            1.0 / ((1i64 << (format.depth() - 1)) - 1) as f64
+        };
+
+        let mut bytes = vec![0; (format.width() / 8) as usize];
+        for &sample in &[-1.0, -0.5, -0.001, 0.0, 0.25, 0.999, 1.0] {
+            write_sample(&format, sample, &mut bytes);
+            let read = read_sample(&format, &bytes);
+            assert!(
+                (read - sample).abs() <= step,
+                "{:?}: wrote {}, read {}",
+                format.format(),
+                sample,
+                read
+            );
+        }
+    }
+
+    #[test]
+    fn round_trips_little_endian() {
+        for &format in &[
+            AudioFormat::S16le,
+            AudioFormat::S24le,
+            AudioFormat::S2432le,
+            AudioFormat::S32le,
+            AudioFormat::U16le,
+            AudioFormat::F32le,
+            AudioFormat::F64le,
+        ] {
+            round_trip(format);
+        }
+    }
+
+    #[test]
+    fn round_trips_big_endian() {
+        for &format in &[
+            AudioFormat::S16be,
+            AudioFormat::S24be,
+            AudioFormat::S2432be,
+            AudioFormat::S32be,
+            AudioFormat::U16be,
+            AudioFormat::F32be,
+            AudioFormat::F64be,
+        ] {
+            round_trip(format);
+        }
+    }
+
+    #[test]
+    fn big_endian_is_little_endian_reversed() {
+        gst::init().unwrap();
+        for &(little, big) in &[
+            (AudioFormat::S16le, AudioFormat::S16be),
+            (AudioFormat::S24le, AudioFormat::S24be),
+            (AudioFormat::S32le, AudioFormat::S32be),
+            (AudioFormat::F32le, AudioFormat::F32be),
+            (AudioFormat::F64le, AudioFormat::F64be),
+        ] {
+            let little = gst_audio::AudioFormatInfo::from_format(little);
+            let big = gst_audio::AudioFormatInfo::from_format(big);
+            let mut little_bytes = vec![0; (little.width() / 8) as usize];
+            let mut big_bytes = little_bytes.clone();
+
+            for &sample in &[-1.0, -0.3, 0.0, 0.7, 1.0] {
+                write_sample(&little, sample, &mut little_bytes);
+                write_sample(&big, sample, &mut big_bytes);
+                big_bytes.reverse();
+                assert_eq!(little_bytes, big_bytes, "{:?}: {}", little.format(), sample);
+            }
+        }
+    }
+
+    #[test]
+    fn writes_big_endian_bytes() {
+        gst::init().unwrap();
+        let format = gst_audio::AudioFormatInfo::from_format(AudioFormat::S16be);
+        let mut bytes = [0; 2];
+        write_sample(&format, 1.0, &mut bytes);
+        assert_eq!(bytes, [0x7f, 0xff]);
+        write_sample(&format, -1.0, &mut bytes);
+        assert_eq!(bytes, [0x80, 0x01]);
+    }
+
+    #[test]
+    fn generators_stay_in_range() {
+        for name in WAVEFORMS {
+            let spec = match *name {
+                "psychedelic" => name.to_string(),
+                "chirp" => "chirp:from=20,to=20000,duration=0.5,amplitude=1".to_string(),
+                _ => format!("{}:amplitude=1", name),
+            };
+            let mut generator = from_spec(&spec, 48000).unwrap();
+
+            // Twenty seconds in buffers of 10 ms, psychedelic only reaches full scale after a while
+            for _ in 0..2000 {
+                generator.start_buffer();
+                for _ in 0..480 {
+                    let sample = generator.next_sample();
+                    assert!((-1.0..=1.0).contains(&sample), "{}: {}", name, sample);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pink_noise_peaks_are_clipped() {
+        // This seed makes the filter go well past its usual gain
+        let mut generator = PinkNoise {
+            amplitude: 1.0,
+            random: Random(12345),
+            b: [0.0; 7],
+        };
+        let peak = (0..200_000)
+            .map(|_| generator.next_sample().abs())
+            .fold(0.0, f64::max);
+        assert_eq!(peak, 1.0);
+    }
+
+    #[test]
+    fn parses_parameters() {
+        assert!(from_args(&args(&["app"]), 48000).is_ok());
+        assert!(from_args(
+            &args(&["app", "--wave", "sine:freq=220,amplitude=0.3"]),
+            48000
+        )
+        .is_ok());
+        assert!(from_args(&args(&["app", "--wave", "chirp:from=100,to=8000"]), 48000).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_waves_and_parameters() {
+        let error = |spec: &str| from_args(&args(&["app", "--wave", spec]), 48000).unwrap_err();
+
+        assert!(error("noise").starts_with("unknown waveform noise"));
+        assert!(error("sine:frequency=220").starts_with("unknown parameter frequency"));
+        assert!(error("white-noise:freq=220").starts_with("unknown parameter freq"));
+        assert_eq!(
+            error("psychedelic:freq=220"),
+            "psychedelic takes no parameters, got freq"
+        );
+        assert_eq!(error("sine:freq"), "invalid parameter 'freq'");
+        assert_eq!(error("sine:freq=high"), "invalid value for freq: high");
+        assert_eq!(
+            error("sine:amplitude=2"),
+            "the amplitude must be between 0 and 1"
+        );
+        assert_eq!(
+            error("chirp:to=0"),
+            "chirp needs positive from, to and duration"
+        );
+        assert!(from_args(&args(&["app", "--wave"]), 48000).is_err());
+    }
+}